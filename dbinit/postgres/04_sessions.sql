@@ -0,0 +1,6 @@
+CREATE TABLE sessions (
+    session_key TEXT NOT NULL PRIMARY KEY,
+    state TEXT NOT NULL,
+    expires_at timestamptz NOT NULL
+);
+CREATE INDEX sessions_expires_at ON sessions (expires_at);
@@ -0,0 +1,16 @@
+-- Tokens are now stored as hex-encoded SHA-256 hashes and expire. Existing
+-- plaintext tokens are hashed in place and given a day to be used.
+ALTER TABLE subscription_tokens
+  RENAME COLUMN subscription_token TO subscription_token_hash;
+
+UPDATE subscription_tokens
+SET subscription_token_hash = encode(sha256(subscription_token_hash::bytea), 'hex');
+
+ALTER TABLE subscription_tokens
+  ADD COLUMN created_at timestamptz NOT NULL DEFAULT now(),
+  ADD COLUMN expires_at timestamptz NOT NULL DEFAULT now() + interval '1 day';
+
+ALTER TABLE subscription_tokens
+  ALTER COLUMN expires_at DROP DEFAULT;
+
+CREATE INDEX subscription_tokens_subscriber_id_idx ON subscription_tokens (subscriber_id);
@@ -0,0 +1,10 @@
+-- Token buckets shared by every instance when rate_limit.store is "postgres".
+-- Buckets that have refilled completely carry no state and are pruned.
+CREATE TABLE rate_limit_buckets (
+    key TEXT NOT NULL PRIMARY KEY,
+    tokens DOUBLE PRECISION NOT NULL,
+    updated_at timestamptz NOT NULL,
+    full_at timestamptz NOT NULL
+);
+
+CREATE INDEX rate_limit_buckets_full_at_idx ON rate_limit_buckets (full_at);
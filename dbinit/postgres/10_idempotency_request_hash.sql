@@ -0,0 +1,3 @@
+-- What the key was first used for, so it can't be replayed for another
+-- request. Keys claimed before this column existed have none.
+ALTER TABLE idempotency ADD COLUMN request_hash BYTEA;
@@ -0,0 +1,8 @@
+-- Form tokens that signed someone up, kept until they expire so that each one
+-- is only good for a single signup.
+CREATE TABLE used_form_tokens (
+    token TEXT PRIMARY KEY,
+    expires_at timestamptz NOT NULL
+);
+
+CREATE INDEX used_form_tokens_expires_at ON used_form_tokens (expires_at);
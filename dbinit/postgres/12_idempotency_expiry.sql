@@ -0,0 +1,7 @@
+-- Keys are forgotten once they expire. Anonymous keys are owned by the
+-- client's address, so without this the table would only ever grow.
+ALTER TABLE idempotency
+    ADD COLUMN expires_at timestamptz NOT NULL DEFAULT now() + interval '1 day';
+ALTER TABLE idempotency ALTER COLUMN expires_at DROP DEFAULT;
+
+CREATE INDEX idempotency_expires_at ON idempotency (expires_at);
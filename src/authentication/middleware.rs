@@ -0,0 +1,83 @@
+use std::ops::Deref;
+
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    error::InternalError,
+    http::header::{self, LOCATION},
+    web, FromRequest, HttpMessage, HttpResponse,
+};
+use actix_web_lab::middleware::Next;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::{basic_authentication, validate_credentials, AuthError};
+use crate::session_state::TypedSession;
+
+#[derive(Copy, Clone, Debug)]
+pub struct UserId(Uuid);
+
+impl std::fmt::Display for UserId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Deref for UserId {
+    type Target = Uuid;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// Lets a request through if it belongs to a logged-in session or carries valid
+/// HTTP Basic credentials; humans without either are sent to the login form.
+pub async fn reject_anonymous_users(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let session = {
+        let (http_request, payload) = req.parts_mut();
+        TypedSession::from_request(http_request, payload).await
+    }?;
+
+    let user_id = match session
+        .get_user_id()
+        .map_err(actix_web::error::ErrorInternalServerError)?
+    {
+        Some(user_id) => user_id,
+        None if req.headers().contains_key(header::AUTHORIZATION) => basic_user_id(&req).await?,
+        None => {
+            let response = HttpResponse::SeeOther()
+                .insert_header((LOCATION, "/login"))
+                .finish();
+            let e = anyhow::anyhow!("The user has not logged in");
+            return Err(InternalError::from_response(e, response).into());
+        }
+    };
+
+    req.extensions_mut().insert(UserId(user_id));
+    next.call(req).await
+}
+
+async fn basic_user_id(req: &ServiceRequest) -> Result<Uuid, actix_web::Error> {
+    let unauthorized = |e: anyhow::Error| -> actix_web::Error {
+        let response = HttpResponse::Unauthorized()
+            .insert_header((header::WWW_AUTHENTICATE, r#"Basic realm="admin""#))
+            .finish();
+        InternalError::from_response(e, response).into()
+    };
+
+    let credentials = basic_authentication(req.headers()).map_err(unauthorized)?;
+    let db_pool = req
+        .app_data::<web::Data<PgPool>>()
+        .ok_or_else(|| actix_web::error::ErrorInternalServerError("Missing database pool"))?;
+
+    validate_credentials(credentials, db_pool)
+        .await
+        .map_err(|e| match e {
+            AuthError::InvalidCredentials(e) => unauthorized(e),
+            AuthError::UnexpectedError(e) => actix_web::error::ErrorInternalServerError(e),
+        })
+}
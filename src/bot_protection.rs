@@ -0,0 +1,400 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use anyhow::Context;
+use chrono::{DateTime, TimeZone, Utc};
+use hmac::{Hmac, Mac};
+use rand::{rngs::OsRng, RngCore};
+use secrecy::{ExposeSecret, Secret};
+use sha2::{Digest, Sha256};
+use sqlx::{Postgres, Transaction};
+
+/// Why a signup was taken for a bot. Bots are never told: the submission is
+/// answered as if it succeeded.
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum BotCheckError {
+    #[error("the honeypot field was filled in")]
+    Honeypot,
+    #[error("the form token is missing")]
+    MissingFormToken,
+    #[error("the form token is malformed")]
+    MalformedFormToken,
+    #[error("the form token signature does not match")]
+    InvalidSignature,
+    #[error("the form was submitted too quickly after being loaded")]
+    SubmittedTooFast,
+    #[error("the form token has expired")]
+    ExpiredFormToken,
+    #[error("the proof of work is missing or insufficient")]
+    InsufficientWork,
+    #[error("the form token was already used")]
+    ReplayedFormToken,
+}
+
+impl BotCheckError {
+    /// A short label for metrics.
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Self::Honeypot => "honeypot",
+            Self::MissingFormToken => "missing_form_token",
+            Self::MalformedFormToken | Self::InvalidSignature => "invalid_form_token",
+            Self::SubmittedTooFast => "too_fast",
+            Self::ExpiredFormToken => "expired_form_token",
+            Self::InsufficientWork => "insufficient_work",
+            Self::ReplayedFormToken => "replayed_form_token",
+        }
+    }
+}
+
+/// The parts of a signup that tell humans and bots apart.
+pub struct Submission<'a> {
+    pub email: &'a str,
+    /// A field hidden from humans, so only bots fill it in.
+    pub honeypot: Option<&'a str>,
+    pub form_token: Option<&'a str>,
+    pub proof_of_work: Option<&'a str>,
+}
+
+/// Issues and checks form tokens. A token carries the time the form was
+/// loaded, signed so it can be verified without storing anything, and doubles
+/// as the challenge of the optional proof of work. Tokens that led to a signup
+/// are recorded by `spend_form_token`, so each one is only good once.
+#[derive(Clone)]
+pub struct BotProtection {
+    hmac_secret: Secret<String>,
+    spent_tokens: Arc<AtomicU64>,
+    pub enabled: bool,
+    pub require_form_token: bool,
+    pub min_submit_time: Duration,
+    pub max_form_age: Duration,
+    /// Leading zero bits required of the proof of work, 0 when none is needed.
+    pub proof_of_work_difficulty: u8,
+}
+
+impl BotProtection {
+    pub fn new(hmac_secret: Secret<String>) -> Self {
+        Self {
+            hmac_secret,
+            spent_tokens: Arc::new(AtomicU64::new(0)),
+            enabled: true,
+            require_form_token: false,
+            min_submit_time: Duration::from_secs(3),
+            max_form_age: Duration::from_secs(86_400),
+            proof_of_work_difficulty: 0,
+        }
+    }
+
+    pub fn form_token(&self) -> String {
+        self.form_token_at(Utc::now())
+    }
+
+    fn form_token_at(&self, issued_at: DateTime<Utc>) -> String {
+        let mut nonce = [0u8; 12];
+        OsRng.fill_bytes(&mut nonce);
+        let payload = format!(
+            "{}.{}",
+            issued_at.timestamp(),
+            base64::encode_config(nonce, base64::URL_SAFE_NO_PAD)
+        );
+        let signature = self.mac(&payload).finalize().into_bytes();
+        format!(
+            "{}.{}",
+            payload,
+            base64::encode_config(signature, base64::URL_SAFE_NO_PAD)
+        )
+    }
+
+    pub fn check(&self, submission: &Submission) -> Result<(), BotCheckError> {
+        if !self.enabled {
+            return Ok(());
+        }
+        if submission.honeypot.is_some_and(|value| !value.is_empty()) {
+            return Err(BotCheckError::Honeypot);
+        }
+
+        let form_token = match submission.form_token.filter(|t| !t.is_empty()) {
+            Some(form_token) => form_token,
+            None if self.require_form_token || self.proof_of_work_difficulty > 0 => {
+                return Err(BotCheckError::MissingFormToken)
+            }
+            None => return Ok(()),
+        };
+        let issued_at = self.verify(form_token)?;
+        let age = (Utc::now() - issued_at).to_std().unwrap_or_default();
+        if age < self.min_submit_time {
+            return Err(BotCheckError::SubmittedTooFast);
+        }
+        if age > self.max_form_age {
+            return Err(BotCheckError::ExpiredFormToken);
+        }
+
+        if self.proof_of_work_difficulty > 0 {
+            let solution = submission
+                .proof_of_work
+                .ok_or(BotCheckError::InsufficientWork)?;
+            if work_done(form_token, submission.email, solution)
+                < u32::from(self.proof_of_work_difficulty)
+            {
+                return Err(BotCheckError::InsufficientWork);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Used tokens that have expired are deleted once every this many signups.
+    const PRUNE_EVERY: u64 = 1_000;
+
+    /// Records a form token that passed `check` as used, returning `false` if
+    /// it already was. This happens in the signup's transaction, so a signup
+    /// that fails can be retried with the same form.
+    #[tracing::instrument(name = "Spending a form token", skip_all)]
+    pub async fn spend_form_token(
+        &self,
+        txn: &mut Transaction<'_, Postgres>,
+        form_token: &str,
+    ) -> Result<bool, anyhow::Error> {
+        let issued_at = self.verify(form_token)?;
+        let expires_at = issued_at
+            + chrono::Duration::from_std(self.max_form_age).context("The form age is too long")?;
+
+        if self
+            .spent_tokens
+            .fetch_add(1, Ordering::Relaxed)
+            .is_multiple_of(Self::PRUNE_EVERY)
+        {
+            sqlx::query!("DELETE FROM used_form_tokens WHERE expires_at < now()")
+                .execute(&mut *txn)
+                .await
+                .context("Failed to prune used form tokens")?;
+        }
+
+        // Only the signed payload is kept: the signature adds nothing to it.
+        let (payload, _signature) = form_token
+            .rsplit_once('.')
+            .ok_or(BotCheckError::MalformedFormToken)?;
+        let n_inserted_rows = sqlx::query!(
+            r#"
+            INSERT INTO used_form_tokens (token, expires_at)
+            VALUES ($1, $2)
+            ON CONFLICT DO NOTHING
+            "#,
+            payload,
+            expires_at,
+        )
+        .execute(&mut *txn)
+        .await
+        .context("Failed to record the form token as used")?
+        .rows_affected();
+
+        Ok(n_inserted_rows > 0)
+    }
+
+    /// Returns when the form token was issued.
+    fn verify(&self, form_token: &str) -> Result<DateTime<Utc>, BotCheckError> {
+        let (payload, signature) = form_token
+            .rsplit_once('.')
+            .ok_or(BotCheckError::MalformedFormToken)?;
+        let signature = base64::decode_config(signature, base64::URL_SAFE_NO_PAD)
+            .map_err(|_| BotCheckError::MalformedFormToken)?;
+        self.mac(payload)
+            .verify_slice(&signature)
+            .map_err(|_| BotCheckError::InvalidSignature)?;
+
+        let (issued_at, _nonce) = payload
+            .split_once('.')
+            .ok_or(BotCheckError::MalformedFormToken)?;
+        issued_at
+            .parse::<i64>()
+            .ok()
+            .and_then(|timestamp| Utc.timestamp_opt(timestamp, 0).single())
+            .ok_or(BotCheckError::MalformedFormToken)
+    }
+
+    fn mac(&self, payload: &str) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.hmac_secret.expose_secret().as_bytes())
+            .expect("HMAC accepts keys of any length");
+        mac.update(b"form:");
+        mac.update(payload.as_bytes());
+        mac
+    }
+}
+
+/// The number of leading zero bits of `SHA-256(form_token:email:solution)`.
+/// Binding the email means one solved challenge can't be reused for a batch of
+/// addresses.
+pub fn work_done(form_token: &str, email: &str, solution: &str) -> u32 {
+    let digest = Sha256::digest(format!("{}:{}:{}", form_token, email, solution).as_bytes());
+    let mut bits = 0;
+    for byte in digest {
+        bits += byte.leading_zeros();
+        if byte != 0 {
+            break;
+        }
+    }
+    bits
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use chrono::Utc;
+    use secrecy::Secret;
+
+    use super::{work_done, BotCheckError, BotProtection, Submission};
+
+    fn protection() -> BotProtection {
+        BotProtection::new(Secret::new(
+            "super-long-and-secret-random-key-needed-to-verify-message-integrity".to_string(),
+        ))
+    }
+
+    fn submission<'a>(
+        form_token: Option<&'a str>,
+        proof_of_work: Option<&'a str>,
+    ) -> Submission<'a> {
+        Submission {
+            email: "ursula_le_guin@gmail.com",
+            honeypot: None,
+            form_token,
+            proof_of_work,
+        }
+    }
+
+    fn solve(form_token: &str, email: &str, difficulty: u32) -> String {
+        (0u64..)
+            .map(|n| n.to_string())
+            .find(|solution| work_done(form_token, email, solution) >= difficulty)
+            .unwrap()
+    }
+
+    #[test]
+    fn a_filled_in_honeypot_is_rejected() {
+        let submission = Submission {
+            honeypot: Some("http://spam.example.com"),
+            ..submission(None, None)
+        };
+
+        assert_eq!(
+            protection().check(&submission),
+            Err(BotCheckError::Honeypot)
+        );
+    }
+
+    #[test]
+    fn forms_submitted_after_the_minimum_time_are_accepted() {
+        let protection = protection();
+        let token = protection.form_token_at(Utc::now() - chrono::Duration::seconds(10));
+
+        assert_eq!(protection.check(&submission(Some(&token), None)), Ok(()));
+    }
+
+    #[test]
+    fn forms_submitted_too_quickly_are_rejected() {
+        let protection = protection();
+        let token = protection.form_token();
+
+        assert_eq!(
+            protection.check(&submission(Some(&token), None)),
+            Err(BotCheckError::SubmittedTooFast)
+        );
+    }
+
+    #[test]
+    fn stale_forms_are_rejected() {
+        let protection = protection();
+        let token = protection.form_token_at(Utc::now() - chrono::Duration::days(2));
+
+        assert_eq!(
+            protection.check(&submission(Some(&token), None)),
+            Err(BotCheckError::ExpiredFormToken)
+        );
+    }
+
+    #[test]
+    fn tampered_form_tokens_are_rejected() {
+        let protection = protection();
+        let token = protection.form_token_at(Utc::now() - chrono::Duration::seconds(10));
+        let (_, rest) = token.split_once('.').unwrap();
+        let forged = format!("{}.{}", Utc::now().timestamp() - 3600, rest);
+
+        assert_eq!(
+            protection.check(&submission(Some(&forged), None)),
+            Err(BotCheckError::InvalidSignature)
+        );
+        assert_eq!(
+            protection.check(&submission(Some("garbage"), None)),
+            Err(BotCheckError::MalformedFormToken)
+        );
+    }
+
+    #[test]
+    fn form_tokens_are_only_required_when_configured() {
+        let mut protection = protection();
+        assert_eq!(protection.check(&submission(None, None)), Ok(()));
+
+        protection.require_form_token = true;
+        assert_eq!(
+            protection.check(&submission(None, None)),
+            Err(BotCheckError::MissingFormToken)
+        );
+    }
+
+    #[test]
+    fn proof_of_work_must_meet_the_difficulty_for_the_submitted_email() {
+        let protection = BotProtection {
+            min_submit_time: Duration::ZERO,
+            proof_of_work_difficulty: 8,
+            ..protection()
+        };
+        let token = protection.form_token();
+        let solution = solve(&token, "ursula_le_guin@gmail.com", 8);
+
+        assert_eq!(
+            protection.check(&submission(Some(&token), Some(&solution))),
+            Ok(())
+        );
+        assert_eq!(
+            protection.check(&submission(Some(&token), None)),
+            Err(BotCheckError::InsufficientWork)
+        );
+
+        // Work done for one address is worth nothing for another.
+        let other = "someone@gmail.com";
+        let solution = (0u64..)
+            .map(|n| n.to_string())
+            .find(|s| {
+                work_done(&token, "ursula_le_guin@gmail.com", s) >= 8
+                    && work_done(&token, other, s) < 8
+            })
+            .unwrap();
+        let submission = Submission {
+            email: other,
+            ..submission(Some(&token), Some(&solution))
+        };
+        assert_eq!(
+            protection.check(&submission),
+            Err(BotCheckError::InsufficientWork)
+        );
+    }
+
+    #[test]
+    fn disabled_protection_accepts_everything() {
+        let protection = BotProtection {
+            enabled: false,
+            ..protection()
+        };
+        let submission = Submission {
+            honeypot: Some("filled"),
+            ..submission(None, None)
+        };
+
+        assert_eq!(protection.check(&submission), Ok(()));
+    }
+}
@@ -1,29 +1,547 @@
+use std::{
+    net::IpAddr,
+    path::{Path, PathBuf},
+};
+
+use argon2::PasswordHash;
+use ipnet::IpNet;
+
 use secrecy::{ExposeSecret, Secret};
+use serde_aux::field_attributes::deserialize_number_from_string;
+use sqlx::{
+    postgres::{PgConnectOptions, PgPoolOptions, PgSslMode},
+    PgPool,
+};
 
-use crate::domain::SubscriberEmail;
+use crate::{
+    bot_protection::BotProtection,
+    domain::{parse_domain_list, EmailPolicy, SubscriberEmail},
+    email_client::{
+        EmailClient, FileTransport, HttpTransport, RetryPolicy, SmtpTls, SmtpTransport,
+    },
+    health::ReadinessProbe,
+    rate_limit::{InMemoryStore, PostgresStore, Quota, RateLimiter},
+};
+
+#[derive(thiserror::Error, Debug)]
+pub enum ConfigurationError {
+    #[error("failed to load configuration")]
+    Load(#[from] config::ConfigError),
+    #[error("invalid configuration:\n  - {}", .0.join("\n  - "))]
+    Invalid(Vec<String>),
+}
 
 #[derive(serde::Deserialize, Clone)]
 pub struct Settings {
+    /// Taken from `APP_ENVIRONMENT` by `get_configuration`.
+    #[serde(default)]
+    pub environment: Environment,
     pub database: DatabaseSettings,
     pub application: ApplicationSettings,
     pub email: EmailSettings,
+    #[serde(default)]
+    pub health: HealthSettings,
+    #[serde(default)]
+    pub telemetry: TelemetrySettings,
+    #[serde(default)]
+    pub rate_limit: RateLimitSettings,
+    #[serde(default)]
+    pub bot_protection: BotProtectionSettings,
+    #[serde(default)]
+    pub email_policy: EmailPolicySettings,
+    #[serde(default)]
+    pub metrics: MetricsSettings,
+    /// The first administrator, created at startup while there are no users.
+    pub admin: Option<AdminSettings>,
+}
+
+#[derive(serde::Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct MetricsSettings {
+    /// Scrapers must send this as `Authorization: Bearer <token>`. `/metrics`
+    /// isn't served at all without one.
+    pub bearer_token: Option<Secret<String>>,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct AdminSettings {
+    pub username: String,
+    /// An Argon2 hash in PHC string format, so the password itself never has
+    /// to be configured.
+    pub password_hash: Secret<String>,
+}
+
+#[derive(serde::Deserialize, Clone, Default)]
+pub struct TelemetrySettings {
+    #[serde(default)]
+    pub format: LogFormat,
+    #[serde(default)]
+    pub redaction: RedactionSettings,
+    /// Spans are exported over OTLP only when this section is present.
+    pub otlp: Option<OtlpSettings>,
+}
+
+#[derive(serde::Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// One bunyan JSON object per line.
+    #[default]
+    Bunyan,
+    /// Human-readable, colored lines for local development.
+    Pretty,
+}
+
+#[derive(serde::Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RedactionPolicy {
+    /// Log a truncated SHA-256, so records about the same value can still be
+    /// correlated.
+    #[default]
+    Hash,
+    /// Keep only the first character and, for emails, the domain.
+    Mask,
+    /// Leave the field out.
+    Drop,
+}
+
+#[derive(serde::Deserialize, Clone)]
+#[serde(default)]
+pub struct RedactionSettings {
+    pub policy: RedactionPolicy,
+    /// Names of the span and event fields the policy applies to.
+    pub fields: Vec<String>,
+}
+
+impl Default for RedactionSettings {
+    fn default() -> Self {
+        Self {
+            policy: RedactionPolicy::default(),
+            fields: ["subscriber_email", "subscriber_name", "subscription_token"]
+                .map(String::from)
+                .to_vec(),
+        }
+    }
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct OtlpSettings {
+    /// gRPC endpoint of the collector, e.g. `http://localhost:4317`.
+    pub endpoint: String,
+    #[serde(default = "default_service_name")]
+    pub service_name: String,
+    /// Fraction of new traces to sample; requests that arrive with a sampled
+    /// `traceparent` are always kept.
+    #[serde(default = "default_sampling_ratio")]
+    pub sampling_ratio: f64,
+}
+
+fn default_service_name() -> String {
+    "zero2prod".into()
+}
+
+fn default_sampling_ratio() -> f64 {
+    1.0
+}
+
+/// Timeouts and optional dependencies of the `/health/ready` check.
+#[derive(serde::Deserialize, Clone)]
+#[serde(default)]
+pub struct HealthSettings {
+    pub database_timeout_milliseconds: u64,
+    /// Whether readiness also requires the HTTP email provider to respond.
+    pub probe_email: bool,
+    pub email_timeout_milliseconds: u64,
+}
+
+impl Default for HealthSettings {
+    fn default() -> Self {
+        Self {
+            database_timeout_milliseconds: 1000,
+            probe_email: false,
+            email_timeout_milliseconds: 2000,
+        }
+    }
+}
+
+impl HealthSettings {
+    pub fn probe(&self, email: &EmailSettings) -> ReadinessProbe {
+        let probe = ReadinessProbe::new(std::time::Duration::from_millis(
+            self.database_timeout_milliseconds,
+        ));
+        if self.probe_email && email.transport == EmailTransportKind::Http {
+            probe.with_email_probe(
+                email.base_url.clone(),
+                std::time::Duration::from_millis(self.email_timeout_milliseconds),
+            )
+        } else {
+            probe
+        }
+    }
+}
+
+/// Which addresses may subscribe, beyond being valid emails.
+#[derive(serde::Deserialize, Clone)]
+#[serde(default)]
+pub struct EmailPolicySettings {
+    /// When not empty, only addresses at these domains may subscribe.
+    pub allowed_domains: Vec<String>,
+    pub denied_domains: Vec<String>,
+    /// One domain per line, read once at startup; changes to the file take
+    /// effect after a restart.
+    pub disposable_domains_file: Option<PathBuf>,
+    /// Loaded from `disposable_domains_file`.
+    #[serde(skip)]
+    pub disposable_domains: Vec<String>,
+    /// Mailboxes such as `postmaster` that belong to a role rather than a
+    /// person. Defaults to the list in `Default`; configuring one replaces
+    /// it.
+    pub role_accounts: Vec<String>,
+}
+
+impl Default for EmailPolicySettings {
+    fn default() -> Self {
+        Self {
+            allowed_domains: Vec::new(),
+            denied_domains: Vec::new(),
+            disposable_domains_file: None,
+            disposable_domains: Vec::new(),
+            role_accounts: [
+                "abuse",
+                "admin",
+                "administrator",
+                "hostmaster",
+                "mailer-daemon",
+                "no-reply",
+                "nobody",
+                "noreply",
+                "postmaster",
+                "root",
+                "security",
+                "webmaster",
+            ]
+            .map(String::from)
+            .to_vec(),
+        }
+    }
+}
+
+impl EmailPolicySettings {
+    pub fn policy(&self) -> EmailPolicy {
+        EmailPolicy::new(self.allowed_domains.clone(), self.denied_domains.clone())
+            .with_disposable_domains(self.disposable_domains.clone())
+            .with_role_accounts(self.role_accounts.clone())
+    }
+}
+
+/// Checks that tell scripted signups apart from people filling in the form.
+#[derive(serde::Deserialize, Clone)]
+#[serde(default)]
+pub struct BotProtectionSettings {
+    pub enabled: bool,
+    /// Reject signups without a form token. Tokens that are sent are checked
+    /// either way, but without one the submit time can't be, so turning this
+    /// off leaves only the honeypot. Off by default, so clients that never
+    /// fetch a token keep working.
+    pub require_form_token: bool,
+    pub min_submit_seconds: u64,
+    pub max_form_age_seconds: u64,
+    /// Leading zero bits the proof of work must have; 0 disables it. Each
+    /// extra bit doubles the work a client has to do.
+    pub proof_of_work_difficulty: u8,
+}
+
+impl Default for BotProtectionSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            require_form_token: false,
+            min_submit_seconds: 3,
+            max_form_age_seconds: 86_400,
+            proof_of_work_difficulty: 0,
+        }
+    }
+}
+
+impl BotProtectionSettings {
+    /// The most work that can be asked of a browser without making people wait.
+    const MAX_PROOF_OF_WORK_DIFFICULTY: u8 = 24;
+
+    pub fn protection(&self, hmac_secret: Secret<String>) -> BotProtection {
+        let mut protection = BotProtection::new(hmac_secret);
+        protection.enabled = self.enabled;
+        protection.require_form_token = self.require_form_token;
+        protection.min_submit_time = std::time::Duration::from_secs(self.min_submit_seconds);
+        protection.max_form_age = std::time::Duration::from_secs(self.max_form_age_seconds);
+        protection.proof_of_work_difficulty = self.proof_of_work_difficulty;
+        protection
+    }
+}
+
+#[derive(serde::Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RateLimitStoreKind {
+    /// Buckets live in each instance, so the limits apply per instance.
+    #[default]
+    Memory,
+    /// Buckets are shared by every instance through the database.
+    Postgres,
+}
+
+/// Token bucket limits on signups and confirmations.
+#[derive(serde::Deserialize, Clone)]
+#[serde(default)]
+pub struct RateLimitSettings {
+    pub enabled: bool,
+    pub store: RateLimitStoreKind,
+    /// Addresses or CIDR ranges of the proxies whose `X-Forwarded-For` header
+    /// is believed.
+    pub trusted_proxies: Vec<String>,
+    pub per_ip: QuotaSettings,
+    pub per_email: QuotaSettings,
+}
+
+#[derive(serde::Deserialize, Clone, Copy)]
+pub struct QuotaSettings {
+    pub capacity: u32,
+    /// Seconds it takes to earn back a single request.
+    pub refill_interval_seconds: f64,
+}
+
+impl Default for RateLimitSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            store: RateLimitStoreKind::default(),
+            trusted_proxies: Vec::new(),
+            per_ip: QuotaSettings {
+                capacity: 10,
+                refill_interval_seconds: 6.0,
+            },
+            per_email: QuotaSettings {
+                capacity: 3,
+                refill_interval_seconds: 1200.0,
+            },
+        }
+    }
+}
+
+impl QuotaSettings {
+    pub fn quota(&self) -> Quota {
+        Quota {
+            capacity: self.capacity,
+            refill_interval: std::time::Duration::from_secs_f64(self.refill_interval_seconds),
+        }
+    }
+
+    fn problems(&self, name: &str) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.capacity == 0 {
+            problems.push(format!("rate_limit.{}.capacity must be positive", name));
+        }
+        if !(self.refill_interval_seconds.is_finite() && self.refill_interval_seconds > 0.0) {
+            problems.push(format!(
+                "rate_limit.{}.refill_interval_seconds must be positive",
+                name
+            ));
+        }
+        problems
+    }
+}
+
+impl RateLimitSettings {
+    /// The limiter to install, or `None` when rate limiting is disabled.
+    pub fn limiter(&self, db_pool: &PgPool) -> anyhow::Result<Option<RateLimiter>> {
+        if !self.enabled {
+            return Ok(None);
+        }
+
+        let trusted_proxies = self.trusted_proxies()?;
+        if trusted_proxies.is_empty() {
+            tracing::warn!(
+                "Rate limiting by peer address as no trusted proxies are configured. \
+                Behind a proxy or load balancer, every client shares its quota: list \
+                it in rate_limit.trusted_proxies."
+            );
+        }
+        let (per_ip, per_email) = (self.per_ip.quota(), self.per_email.quota());
+        let limiter = match self.store {
+            RateLimitStoreKind::Memory => {
+                RateLimiter::new(InMemoryStore::default(), per_ip, per_email, trusted_proxies)
+            }
+            RateLimitStoreKind::Postgres => RateLimiter::new(
+                PostgresStore::new(db_pool.clone()),
+                per_ip,
+                per_email,
+                trusted_proxies,
+            ),
+        };
+        Ok(Some(limiter))
+    }
+
+    pub fn trusted_proxies(&self) -> anyhow::Result<Vec<IpNet>> {
+        self.trusted_proxies
+            .iter()
+            .map(|proxy| {
+                proxy
+                    .parse::<IpNet>()
+                    .or_else(|_| proxy.parse::<IpAddr>().map(IpNet::from))
+                    .map_err(|_| anyhow::anyhow!("{:?} is not an IP address or range", proxy))
+            })
+            .collect()
+    }
 }
 
 #[derive(serde::Deserialize, Clone)]
 pub struct ApplicationSettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
     pub port: u16,
     pub host: String,
+    pub base_url: String,
+    #[serde(default = "empty_secret")]
+    pub hmac_secret: Secret<String>,
+    /// Read at startup in place of `hmac_secret` when set.
+    pub hmac_secret_file: Option<PathBuf>,
+    pub confirmation_token_ttl_seconds: u64,
+}
+
+impl ApplicationSettings {
+    /// The session cookie key is derived from the HMAC secret and needs at
+    /// least this many bytes.
+    pub const MIN_HMAC_SECRET_LENGTH: usize = 64;
+
+    /// The secret checked into `configuration/local.yaml`, which must not sign
+    /// anything outside local development.
+    const LOCAL_HMAC_SECRET: &'static str =
+        "super-long-and-secret-random-key-needed-to-verify-message-integrity";
+
+    pub fn confirmation_token_ttl(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.confirmation_token_ttl_seconds)
+    }
+}
+
+#[derive(serde::Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EmailTransportKind {
+    #[default]
+    Http,
+    Smtp,
+    File,
 }
 
 #[derive(serde::Deserialize, Clone)]
 pub struct EmailSettings {
+    #[serde(default)]
+    pub transport: EmailTransportKind,
     pub sender_email: String,
     pub base_url: String,
+    #[serde(default = "empty_secret")]
     pub authorization_token: Secret<String>,
+    /// Read at startup in place of `authorization_token` when set.
+    pub authorization_token_file: Option<PathBuf>,
     pub timeout_milliseconds: u64,
+    #[serde(default)]
+    pub retry: RetrySettings,
+    pub smtp: Option<SmtpSettings>,
+    pub file: Option<FileSettings>,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct SmtpSettings {
+    pub host: String,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub port: u16,
+    #[serde(default)]
+    pub tls: SmtpTls,
+    pub username: Option<String>,
+    pub password: Option<Secret<String>>,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct FileSettings {
+    pub directory: PathBuf,
+}
+
+#[derive(serde::Deserialize, Clone)]
+#[serde(default)]
+pub struct RetrySettings {
+    pub max_attempts: u32,
+    pub base_delay_milliseconds: u64,
+    pub max_delay_milliseconds: u64,
+    pub jitter: bool,
+    pub retryable_status_codes: Vec<u16>,
+}
+
+impl Default for RetrySettings {
+    fn default() -> Self {
+        let policy = RetryPolicy::default();
+        Self {
+            max_attempts: policy.max_attempts,
+            base_delay_milliseconds: policy.base_delay.as_millis() as u64,
+            max_delay_milliseconds: policy.max_delay.as_millis() as u64,
+            jitter: policy.jitter,
+            retryable_status_codes: policy
+                .retryable_status_codes
+                .iter()
+                .map(|s| s.as_u16())
+                .collect(),
+        }
+    }
+}
+
+impl RetrySettings {
+    pub fn policy(&self) -> anyhow::Result<RetryPolicy> {
+        let retryable_status_codes = self
+            .retryable_status_codes
+            .iter()
+            .map(|&code| reqwest::StatusCode::from_u16(code))
+            .collect::<Result<_, _>>()?;
+
+        Ok(RetryPolicy {
+            max_attempts: self.max_attempts,
+            base_delay: std::time::Duration::from_millis(self.base_delay_milliseconds),
+            max_delay: std::time::Duration::from_millis(self.max_delay_milliseconds),
+            jitter: self.jitter,
+            retryable_status_codes,
+        })
+    }
 }
 
 impl EmailSettings {
+    pub fn client(self) -> anyhow::Result<EmailClient> {
+        let sender = self.sender()?;
+        let timeout = self.timeout();
+
+        let client = match self.transport {
+            EmailTransportKind::Http => {
+                let retry_policy = self.retry.policy()?;
+                let transport = HttpTransport::new(
+                    self.base_url,
+                    self.authorization_token,
+                    timeout,
+                    retry_policy,
+                );
+                EmailClient::new(sender, transport)
+            }
+            EmailTransportKind::Smtp => {
+                let smtp = self.smtp.ok_or_else(|| {
+                    anyhow::anyhow!("email.smtp must be set for the smtp transport")
+                })?;
+                let credentials = smtp.username.zip(smtp.password);
+                let transport =
+                    SmtpTransport::new(&smtp.host, smtp.port, smtp.tls, credentials, timeout)?;
+                EmailClient::new(sender, transport)
+            }
+            EmailTransportKind::File => {
+                let file = self.file.ok_or_else(|| {
+                    anyhow::anyhow!("email.file must be set for the file transport")
+                })?;
+                EmailClient::new(sender, FileTransport::new(file.directory)?)
+            }
+        };
+
+        Ok(client)
+    }
+
     pub fn sender(&self) -> anyhow::Result<SubscriberEmail> {
         Ok(SubscriberEmail::parse(self.sender_email.clone())?)
     }
@@ -36,39 +554,756 @@ impl EmailSettings {
 #[derive(serde::Deserialize, Clone)]
 pub struct DatabaseSettings {
     pub username: String,
+    #[serde(default = "empty_secret")]
     pub password: Secret<String>,
+    /// Read at startup in place of `password` when set.
+    pub password_file: Option<PathBuf>,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
     pub port: u16,
     pub host: String,
     pub database_name: String,
+    #[serde(default)]
+    pub ssl_mode: DatabaseSslMode,
+    /// CA certificate used to verify the server under `verify_ca` and
+    /// `verify_full`.
+    pub ssl_root_cert: Option<PathBuf>,
+    #[serde(default)]
+    pub pool: PoolSettings,
+    #[serde(default)]
+    pub connect: ConnectSettings,
+}
+
+#[derive(serde::Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DatabaseSslMode {
+    Disable,
+    #[default]
+    Prefer,
+    Require,
+    VerifyCa,
+    VerifyFull,
+}
+
+impl From<DatabaseSslMode> for PgSslMode {
+    fn from(mode: DatabaseSslMode) -> Self {
+        match mode {
+            DatabaseSslMode::Disable => PgSslMode::Disable,
+            DatabaseSslMode::Prefer => PgSslMode::Prefer,
+            DatabaseSslMode::Require => PgSslMode::Require,
+            DatabaseSslMode::VerifyCa => PgSslMode::VerifyCa,
+            DatabaseSslMode::VerifyFull => PgSslMode::VerifyFull,
+        }
+    }
+}
+
+#[derive(serde::Deserialize, Clone)]
+#[serde(default)]
+pub struct PoolSettings {
+    pub max_connections: u32,
+    pub min_connections: u32,
+    pub acquire_timeout_milliseconds: u64,
+    /// Connections idle for longer are closed; `None` keeps them open.
+    pub idle_timeout_seconds: Option<u64>,
+}
+
+impl Default for PoolSettings {
+    fn default() -> Self {
+        Self {
+            max_connections: 10,
+            min_connections: 0,
+            acquire_timeout_milliseconds: 5000,
+            idle_timeout_seconds: Some(600),
+        }
+    }
+}
+
+#[derive(serde::Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ConnectStrategy {
+    /// Open connections on first use, so startup never waits for the database.
+    Lazy,
+    /// Connect during startup, retrying with backoff while the database comes up.
+    #[default]
+    Retry,
+}
+
+#[derive(serde::Deserialize, Clone)]
+#[serde(default)]
+pub struct ConnectSettings {
+    pub strategy: ConnectStrategy,
+    pub max_attempts: u32,
+    pub base_delay_milliseconds: u64,
+    pub max_delay_milliseconds: u64,
+}
+
+impl Default for ConnectSettings {
+    fn default() -> Self {
+        Self {
+            strategy: ConnectStrategy::default(),
+            max_attempts: 10,
+            base_delay_milliseconds: 250,
+            max_delay_milliseconds: 5000,
+        }
+    }
+}
+
+impl ConnectSettings {
+    /// Delay before the given retry, doubling from the base delay up to the
+    /// maximum.
+    pub fn delay(&self, retry: u32) -> std::time::Duration {
+        let delay = self
+            .base_delay_milliseconds
+            .saturating_mul(2_u64.saturating_pow(retry.saturating_sub(1)));
+        std::time::Duration::from_millis(delay.min(self.max_delay_milliseconds))
+    }
 }
 
 impl DatabaseSettings {
-    pub fn dsn(&self) -> Secret<String> {
-        Secret::new(format!(
-            "postgres://{}:{}@{}:{}/{}",
-            self.username,
-            self.password.expose_secret(),
-            self.host,
-            self.port,
-            self.database_name
-        ))
+    pub fn without_db(&self) -> PgConnectOptions {
+        let options = PgConnectOptions::new()
+            .host(&self.host)
+            .username(&self.username)
+            .password(self.password.expose_secret())
+            .port(self.port)
+            .ssl_mode(self.ssl_mode.into());
+
+        match &self.ssl_root_cert {
+            Some(path) => options.ssl_root_cert(path),
+            None => options,
+        }
+    }
+
+    pub fn with_db(&self) -> PgConnectOptions {
+        self.without_db().database(&self.database_name)
     }
 
-    pub fn dsn_without_db(&self) -> Secret<String> {
-        Secret::new(format!(
-            "postgres://{}:{}@{}:{}/",
-            self.username,
-            self.password.expose_secret(),
-            self.host,
-            self.port
-        ))
+    pub fn pool_options(&self) -> PgPoolOptions {
+        PgPoolOptions::new()
+            .max_connections(self.pool.max_connections)
+            .min_connections(self.pool.min_connections)
+            .acquire_timeout(std::time::Duration::from_millis(
+                self.pool.acquire_timeout_milliseconds,
+            ))
+            .idle_timeout(
+                self.pool
+                    .idle_timeout_seconds
+                    .map(std::time::Duration::from_secs),
+            )
     }
 }
 
-pub fn get_configuration() -> Result<Settings, config::ConfigError> {
+/// Loads `configuration/base.yaml`, then the file for the environment named by
+/// `APP_ENVIRONMENT` (defaulting to local), then `APP_` environment variables
+/// using `__` to separate nested keys. Secret files are read and the result is
+/// validated before it is returned.
+pub fn get_configuration() -> Result<Settings, ConfigurationError> {
     let mut settings = config::Config::default();
+    let base_path = std::env::current_dir().expect("Failed to determine the current directory");
+    let configuration_directory = base_path.join("configuration");
+
+    let environment: Environment = std::env::var("APP_ENVIRONMENT")
+        .unwrap_or_else(|_| "local".into())
+        .try_into()
+        .map_err(config::ConfigError::Message)?;
+
+    settings.merge(config::File::from(configuration_directory.join("base")).required(true))?;
+    settings.merge(
+        config::File::from(configuration_directory.join(environment.as_str())).required(true),
+    )?;
+    settings.merge(config::Environment::with_prefix("app").separator("__"))?;
+    settings.set("environment", environment.as_str())?;
+
+    let settings: Settings = settings.try_into()?;
+    settings.resolve()
+}
+
+impl Settings {
+    /// Replaces secrets configured as `*_file` paths with the files' contents
+    /// and checks the whole tree, reporting every problem found at once.
+    pub fn resolve(mut self) -> Result<Self, ConfigurationError> {
+        let mut problems = Vec::new();
+
+        if let Some(path) = &self.application.hmac_secret_file {
+            match read_secret_file(path) {
+                Ok(secret) => self.application.hmac_secret = secret,
+                Err(e) => problems.push(format!("application.hmac_secret_file: {}", e)),
+            }
+        }
+        if let Some(path) = &self.database.password_file {
+            match read_secret_file(path) {
+                Ok(password) => self.database.password = password,
+                Err(e) => problems.push(format!("database.password_file: {}", e)),
+            }
+        }
+        if let Some(path) = &self.email.authorization_token_file {
+            match read_secret_file(path) {
+                Ok(token) => self.email.authorization_token = token,
+                Err(e) => problems.push(format!("email.authorization_token_file: {}", e)),
+            }
+        }
+        if let Some(path) = &self.email_policy.disposable_domains_file {
+            match std::fs::read_to_string(path) {
+                Ok(contents) => self.email_policy.disposable_domains = parse_domain_list(&contents),
+                Err(e) => problems.push(format!(
+                    "email_policy.disposable_domains_file: failed to read {}: {}",
+                    path.display(),
+                    e
+                )),
+            }
+        }
+
+        problems.extend(self.problems());
+
+        if problems.is_empty() {
+            Ok(self)
+        } else {
+            Err(ConfigurationError::Invalid(problems))
+        }
+    }
+
+    fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let application = &self.application;
+        let email = &self.email;
+
+        if let Err(e) = check_url(&application.base_url) {
+            problems.push(format!("application.base_url: {}", e));
+        }
+        let hmac_secret = application.hmac_secret.expose_secret();
+        if hmac_secret.is_empty() {
+            problems.push(
+                "application.hmac_secret must be set, e.g. with APP_APPLICATION__HMAC_SECRET \
+                 or application.hmac_secret_file"
+                    .into(),
+            );
+        } else if hmac_secret.len() < ApplicationSettings::MIN_HMAC_SECRET_LENGTH {
+            problems.push(format!(
+                "application.hmac_secret must be at least {} bytes",
+                ApplicationSettings::MIN_HMAC_SECRET_LENGTH
+            ));
+        } else if hmac_secret == ApplicationSettings::LOCAL_HMAC_SECRET
+            && self.environment != Environment::Local
+        {
+            problems.push(format!(
+                "application.hmac_secret is the public local development secret and can't be \
+                 used in {}",
+                self.environment.as_str()
+            ));
+        }
+        if application.confirmation_token_ttl_seconds == 0 {
+            problems.push("application.confirmation_token_ttl_seconds must be positive".into());
+        }
+        if application.port != 0
+            && application.port == self.database.port
+            && same_host(&application.host, &self.database.host)
+        {
+            problems.push(format!(
+                "application.port: {} is already used by the database",
+                application.port
+            ));
+        }
+
+        if let Err(e) = email.sender() {
+            problems.push(format!("email.sender_email: {}", e));
+        }
+        let pool = &self.database.pool;
+        if pool.max_connections == 0 {
+            problems.push("database.pool.max_connections must be positive".into());
+        }
+        if pool.min_connections > pool.max_connections {
+            problems.push(format!(
+                "database.pool.min_connections: {} exceeds max_connections",
+                pool.min_connections
+            ));
+        }
+        if pool.acquire_timeout_milliseconds == 0 {
+            problems.push("database.pool.acquire_timeout_milliseconds must be positive".into());
+        }
+        if self.health.database_timeout_milliseconds == 0 {
+            problems.push("health.database_timeout_milliseconds must be positive".into());
+        }
+        if self.health.probe_email && self.health.email_timeout_milliseconds == 0 {
+            problems.push("health.email_timeout_milliseconds must be positive".into());
+        }
+        if let Some(otlp) = &self.telemetry.otlp {
+            if let Err(e) = check_url(&otlp.endpoint) {
+                problems.push(format!("telemetry.otlp.endpoint: {}", e));
+            }
+            if !(0.0..=1.0).contains(&otlp.sampling_ratio) {
+                problems.push(format!(
+                    "telemetry.otlp.sampling_ratio: {} is not between 0 and 1",
+                    otlp.sampling_ratio
+                ));
+            }
+        }
+        if self.rate_limit.enabled {
+            if let Err(e) = self.rate_limit.trusted_proxies() {
+                problems.push(format!("rate_limit.trusted_proxies: {}", e));
+            }
+            problems.extend(self.rate_limit.per_ip.problems("per_ip"));
+            problems.extend(self.rate_limit.per_email.problems("per_email"));
+        }
+        let bot_protection = &self.bot_protection;
+        if bot_protection.min_submit_seconds >= bot_protection.max_form_age_seconds {
+            problems.push(
+                "bot_protection.min_submit_seconds must be below max_form_age_seconds".into(),
+            );
+        }
+        if bot_protection.proof_of_work_difficulty
+            > BotProtectionSettings::MAX_PROOF_OF_WORK_DIFFICULTY
+        {
+            problems.push(format!(
+                "bot_protection.proof_of_work_difficulty: {} is above the maximum of {}",
+                bot_protection.proof_of_work_difficulty,
+                BotProtectionSettings::MAX_PROOF_OF_WORK_DIFFICULTY
+            ));
+        }
+        if self
+            .metrics
+            .bearer_token
+            .as_ref()
+            .is_some_and(|t| t.expose_secret().is_empty())
+        {
+            problems.push("metrics.bearer_token must not be empty".into());
+        }
+        if let Some(admin) = &self.admin {
+            if admin.username.trim().is_empty() {
+                problems.push("admin.username must be set".into());
+            }
+            if PasswordHash::new(admin.password_hash.expose_secret()).is_err() {
+                problems.push("admin.password_hash is not a hash in PHC string format".into());
+            }
+        }
+        if self.database.connect.max_attempts == 0 {
+            problems.push("database.connect.max_attempts must be positive".into());
+        }
+
+        if email.timeout_milliseconds == 0 {
+            problems.push("email.timeout_milliseconds must be positive".into());
+        }
+        match email.transport {
+            EmailTransportKind::Http => {
+                if let Err(e) = check_url(&email.base_url) {
+                    problems.push(format!("email.base_url: {}", e));
+                }
+                if email.authorization_token.expose_secret().is_empty() {
+                    problems.push("email.authorization_token must be set".into());
+                }
+                if email.retry.max_attempts == 0 {
+                    problems.push("email.retry.max_attempts must be positive".into());
+                }
+                if let Err(e) = email.retry.policy() {
+                    problems.push(format!("email.retry.retryable_status_codes: {}", e));
+                }
+            }
+            EmailTransportKind::Smtp => match &email.smtp {
+                Some(smtp) => {
+                    if smtp.port == 0 {
+                        problems.push("email.smtp.port must be positive".into());
+                    }
+                    if smtp.username.is_some() != smtp.password.is_some() {
+                        problems.push(
+                            "email.smtp.username and email.smtp.password must be set together"
+                                .into(),
+                        );
+                    }
+                    if smtp.port == application.port && same_host(&smtp.host, &application.host) {
+                        problems.push(format!(
+                            "email.smtp.port: {} is already used by the application",
+                            smtp.port
+                        ));
+                    }
+                }
+                None => problems.push("email.smtp must be set for the smtp transport".into()),
+            },
+            EmailTransportKind::File => {
+                if email.file.is_none() {
+                    problems.push("email.file must be set for the file transport".into());
+                }
+            }
+        }
+
+        problems
+    }
+}
+
+fn empty_secret() -> Secret<String> {
+    Secret::new(String::new())
+}
+
+fn read_secret_file(path: &Path) -> Result<Secret<String>, String> {
+    std::fs::read_to_string(path)
+        .map(|s| Secret::new(s.trim_end_matches(['\r', '\n']).to_string()))
+        .map_err(|e| format!("failed to read {}: {}", path.display(), e))
+}
+
+fn check_url(url: &str) -> Result<(), String> {
+    let parsed =
+        reqwest::Url::parse(url).map_err(|e| format!("{} is not a valid URL: {}", url, e))?;
+    match parsed.scheme() {
+        "http" | "https" => Ok(()),
+        scheme => Err(format!("{} must use http or https, not {}", url, scheme)),
+    }
+}
+
+fn same_host(a: &str, b: &str) -> bool {
+    let local = ["localhost", "127.0.0.1", "::1", "0.0.0.0"];
+    a.eq_ignore_ascii_case(b)
+        || (local.contains(&a.to_lowercase().as_str())
+            && local.contains(&b.to_lowercase().as_str()))
+}
+
+/// The runtime environment the application is deployed to.
+#[derive(serde::Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub enum Environment {
+    #[default]
+    Local,
+    Staging,
+    Production,
+}
+
+impl Environment {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Environment::Local => "local",
+            Environment::Staging => "staging",
+            Environment::Production => "production",
+        }
+    }
+}
+
+impl TryFrom<String> for Environment {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        match s.to_lowercase().as_str() {
+            "local" => Ok(Self::Local),
+            "staging" => Ok(Self::Staging),
+            "production" => Ok(Self::Production),
+            other => Err(format!(
+                "{} is not a supported environment. Use `local`, `staging` or `production`.",
+                other
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claim::{assert_err, assert_ok_eq};
+    use secrecy::{ExposeSecret, Secret};
+
+    use super::{
+        AdminSettings, ApplicationSettings, ConfigurationError, ConnectSettings,
+        EmailTransportKind, Environment, Settings, SmtpSettings, SmtpTls,
+    };
+
+    const HMAC_SECRET: &str = "super-long-and-secret-random-key-needed-to-verify-message-integrity";
+
+    fn settings() -> Settings {
+        serde_json::from_value(serde_json::json!({
+            "application": {
+                "port": 8000,
+                "host": "localhost",
+                "base_url": "http://localhost:8000",
+                "hmac_secret": HMAC_SECRET,
+                "confirmation_token_ttl_seconds": 60,
+            },
+            "database": {
+                "username": "postgres",
+                "password": "postgres",
+                "port": 5432,
+                "host": "localhost",
+                "database_name": "newsletter",
+            },
+            "email": {
+                "sender_email": "hello@example.com",
+                "base_url": "http://localhost:8025",
+                "authorization_token": "token",
+                "timeout_milliseconds": 1000,
+            },
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn environment_names_are_case_insensitive() {
+        assert_ok_eq!(
+            Environment::try_from("Production".to_string()),
+            Environment::Production
+        );
+        assert_ok_eq!(
+            Environment::try_from("local".to_string()),
+            Environment::Local
+        );
+        assert_ok_eq!(
+            Environment::try_from("STAGING".to_string()),
+            Environment::Staging
+        );
+    }
+
+    #[test]
+    fn every_environment_file_deserializes() {
+        for environment in [
+            Environment::Local,
+            Environment::Staging,
+            Environment::Production,
+        ] {
+            let mut config = config::Config::default();
+            config
+                .merge(config::File::with_name("configuration/base"))
+                .unwrap();
+            config
+                .merge(config::File::with_name(&format!(
+                    "configuration/{}",
+                    environment.as_str()
+                )))
+                .unwrap();
+
+            if let Err(e) = config.try_into::<Settings>() {
+                panic!("{}: {}", environment.as_str(), e);
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_environment_is_rejected() {
+        assert_err!(Environment::try_from("testing".to_string()));
+    }
+
+    #[test]
+    fn ports_can_be_given_as_strings() {
+        let settings: ApplicationSettings = serde_json::from_value(serde_json::json!({
+            "port": "8080",
+            "host": "0.0.0.0",
+            "base_url": "http://localhost",
+            "hmac_secret": HMAC_SECRET,
+            "confirmation_token_ttl_seconds": 60,
+        }))
+        .unwrap();
+
+        assert_eq!(settings.port, 8080);
+    }
+
+    #[test]
+    fn valid_settings_are_accepted() {
+        assert!(settings().resolve().is_ok());
+    }
+
+    #[test]
+    fn every_problem_is_reported_at_once() {
+        let mut settings = settings();
+        settings.email.sender_email = "not an email".into();
+        settings.email.timeout_milliseconds = 0;
+        settings.email.base_url = "localhost".into();
+        settings.application.port = 5432;
+
+        match settings.resolve() {
+            Err(ConfigurationError::Invalid(problems)) => {
+                assert_eq!(problems.len(), 4, "{:?}", problems);
+            }
+            _ => panic!("expected the settings to be rejected"),
+        }
+    }
+
+    #[test]
+    fn short_hmac_secrets_are_rejected() {
+        let mut settings = settings();
+        settings.application.hmac_secret = Secret::new("secret".to_string());
+
+        match settings.resolve() {
+            Err(ConfigurationError::Invalid(problems)) => {
+                assert_eq!(
+                    problems,
+                    vec!["application.hmac_secret must be at least 64 bytes".to_string()]
+                );
+            }
+            _ => panic!("expected the settings to be rejected"),
+        }
+    }
+
+    #[test]
+    fn a_missing_hmac_secret_is_rejected() {
+        let mut settings = settings();
+        settings.application.hmac_secret = Secret::new(String::new());
+
+        assert!(settings.resolve().is_err());
+    }
+
+    #[test]
+    fn the_local_hmac_secret_is_rejected_outside_local() {
+        let mut settings = settings();
+        settings.environment = Environment::Production;
+
+        match settings.resolve() {
+            Err(ConfigurationError::Invalid(problems)) => {
+                assert_eq!(
+                    problems,
+                    vec![
+                        "application.hmac_secret is the public local development secret and \
+                         can't be used in production"
+                            .to_string()
+                    ]
+                );
+            }
+            _ => panic!("expected the settings to be rejected"),
+        }
+    }
+
+    #[test]
+    fn the_hmac_secret_is_read_from_a_file() {
+        let path = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        std::fs::write(&path, format!("{}-from-a-file\n", HMAC_SECRET)).unwrap();
+        let mut settings = settings();
+        settings.environment = Environment::Production;
+        settings.application.hmac_secret = Secret::new(String::new());
+        settings.application.hmac_secret_file = Some(path.clone());
+
+        let settings = settings.resolve();
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(
+            settings.unwrap().application.hmac_secret.expose_secret(),
+            &format!("{}-from-a-file", HMAC_SECRET)
+        );
+    }
 
-    settings.merge(config::File::with_name("configuration"))?;
+    #[test]
+    fn admin_passwords_must_be_given_as_hashes() {
+        let mut settings = settings();
+        settings.admin = Some(AdminSettings {
+            username: "admin".into(),
+            password_hash: Secret::new("everythinghastostartsomewhere".into()),
+        });
 
-    settings.try_into()
+        assert!(settings.resolve().is_err());
+    }
+
+    #[test]
+    fn email_retries_need_at_least_one_attempt() {
+        let mut settings = settings();
+        settings.email.retry.max_attempts = 0;
+
+        match settings.resolve() {
+            Err(ConfigurationError::Invalid(problems)) => {
+                assert_eq!(
+                    problems,
+                    vec!["email.retry.max_attempts must be positive".to_string()]
+                );
+            }
+            _ => panic!("expected the settings to be rejected"),
+        }
+    }
+
+    #[test]
+    fn smtp_credentials_must_be_given_together() {
+        let mut settings = settings();
+        settings.email.transport = EmailTransportKind::Smtp;
+        settings.email.smtp = Some(SmtpSettings {
+            host: "smtp.example.com".into(),
+            port: 587,
+            tls: SmtpTls::Starttls,
+            username: Some("mailer".into()),
+            password: None,
+        });
+
+        match settings.resolve() {
+            Err(ConfigurationError::Invalid(problems)) => {
+                assert_eq!(
+                    problems,
+                    vec![
+                        "email.smtp.username and email.smtp.password must be set together"
+                            .to_string()
+                    ]
+                );
+            }
+            _ => panic!("expected the settings to be rejected"),
+        }
+    }
+
+    #[test]
+    fn secrets_are_read_from_files() {
+        let path = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        std::fs::write(&path, "from-a-file\n").unwrap();
+        let mut settings = settings();
+        settings.database.password_file = Some(path.clone());
+
+        let settings = settings.resolve();
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(
+            settings.unwrap().database.password.expose_secret(),
+            "from-a-file"
+        );
+    }
+
+    #[test]
+    fn missing_secret_files_are_reported() {
+        let mut settings = settings();
+        settings.email.authorization_token_file = Some("/does/not/exist".into());
+
+        assert!(settings.resolve().is_err());
+    }
+
+    #[test]
+    fn connect_retries_back_off_up_to_the_maximum_delay() {
+        let connect = ConnectSettings {
+            base_delay_milliseconds: 100,
+            max_delay_milliseconds: 350,
+            ..ConnectSettings::default()
+        };
+
+        let delays: Vec<u128> = (1..=4).map(|n| connect.delay(n).as_millis()).collect();
+
+        assert_eq!(delays, vec![100, 200, 350, 350]);
+    }
+
+    #[test]
+    fn pool_limits_are_validated() {
+        let mut settings = settings();
+        settings.database.pool.max_connections = 0;
+        settings.database.pool.min_connections = 1;
+
+        match settings.resolve() {
+            Err(ConfigurationError::Invalid(problems)) => assert_eq!(problems.len(), 2),
+            _ => panic!("expected the settings to be rejected"),
+        }
+    }
+
+    #[test]
+    fn trusted_proxies_accept_addresses_and_ranges() {
+        let mut settings = settings();
+        settings.rate_limit.trusted_proxies = vec!["10.0.0.0/8".into(), "::1".into()];
+        assert_eq!(settings.rate_limit.trusted_proxies().unwrap().len(), 2);
+
+        settings
+            .rate_limit
+            .trusted_proxies
+            .push("proxy.internal".into());
+        settings.rate_limit.per_email.refill_interval_seconds = 0.0;
+        match settings.resolve() {
+            Err(ConfigurationError::Invalid(problems)) => assert_eq!(problems.len(), 2),
+            _ => panic!("expected the settings to be rejected"),
+        }
+    }
+
+    #[test]
+    fn disposable_domains_are_read_from_a_file() {
+        let path = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        std::fs::write(&path, "# throwaway\nmailinator.com\n").unwrap();
+        let mut settings = settings();
+        settings.email_policy.disposable_domains_file = Some(path.clone());
+
+        let settings = settings.resolve();
+        std::fs::remove_file(path).unwrap();
+        let mut settings = settings.expect("Failed to resolve the settings");
+        assert_eq!(
+            settings.email_policy.disposable_domains,
+            vec!["mailinator.com"]
+        );
+
+        settings.email_policy.disposable_domains_file = Some("/does/not/exist".into());
+        assert!(settings.resolve().is_err());
+    }
 }
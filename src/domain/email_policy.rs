@@ -0,0 +1,171 @@
+use std::collections::HashSet;
+
+use super::{EmailError, SubscriberEmail};
+
+/// Which addresses may subscribe, on top of being syntactically valid.
+///
+/// Domains match themselves and their subdomains, so denying `example.com`
+/// also denies `mail.example.com`.
+#[derive(Clone, Debug, Default)]
+pub struct EmailPolicy {
+    /// When not empty, only these domains are accepted.
+    allowed_domains: HashSet<String>,
+    denied_domains: HashSet<String>,
+    disposable_domains: HashSet<String>,
+    role_accounts: HashSet<String>,
+}
+
+impl EmailPolicy {
+    pub fn new(
+        allowed_domains: impl IntoIterator<Item = String>,
+        denied_domains: impl IntoIterator<Item = String>,
+    ) -> Self {
+        Self {
+            allowed_domains: normalize(allowed_domains),
+            denied_domains: normalize(denied_domains),
+            ..Self::default()
+        }
+    }
+
+    pub fn with_disposable_domains(mut self, domains: impl IntoIterator<Item = String>) -> Self {
+        self.disposable_domains = normalize(domains);
+        self
+    }
+
+    pub fn with_role_accounts(mut self, mailboxes: impl IntoIterator<Item = String>) -> Self {
+        self.role_accounts = normalize(mailboxes);
+        self
+    }
+
+    pub fn check(&self, email: &SubscriberEmail) -> Result<(), EmailError> {
+        let domain = email.domain();
+
+        let explicitly_allowed = matches(&self.allowed_domains, &domain);
+        if !self.allowed_domains.is_empty() && !explicitly_allowed {
+            return Err(EmailError::DomainNotAllowed(domain));
+        }
+        if matches(&self.denied_domains, &domain) {
+            return Err(EmailError::DomainDenied(domain));
+        }
+        // Listing a domain as allowed overrides the disposable list.
+        if !explicitly_allowed && matches(&self.disposable_domains, &domain) {
+            return Err(EmailError::DisposableDomain(domain));
+        }
+
+        let mailbox = email.mailbox();
+        if self.role_accounts.contains(&mailbox) {
+            return Err(EmailError::RoleAccount(mailbox));
+        }
+
+        Ok(())
+    }
+}
+
+/// Reads a domain list with one domain per line. Blank lines and `#` comments
+/// are ignored.
+pub fn parse_domain_list(contents: &str) -> Vec<String> {
+    contents
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+fn normalize(values: impl IntoIterator<Item = String>) -> HashSet<String> {
+    values
+        .into_iter()
+        .map(|v| v.trim().trim_end_matches('.').to_lowercase())
+        .filter(|v| !v.is_empty())
+        .collect()
+}
+
+/// Whether `domain` or any of its parent domains is in `domains`.
+fn matches(domains: &HashSet<String>, domain: &str) -> bool {
+    let mut candidate = domain;
+    loop {
+        if domains.contains(candidate) {
+            return true;
+        }
+        match candidate.split_once('.') {
+            Some((_, parent)) => candidate = parent,
+            None => return false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claim::assert_ok;
+
+    use super::{parse_domain_list, EmailPolicy};
+    use crate::domain::{EmailError, SubscriberEmail};
+
+    fn email(s: &str) -> SubscriberEmail {
+        SubscriberEmail::parse(s.to_string()).unwrap()
+    }
+
+    fn domains(list: &[&str]) -> Vec<String> {
+        list.iter().map(|d| d.to_string()).collect()
+    }
+
+    #[test]
+    fn an_empty_policy_accepts_everything() {
+        assert_ok!(EmailPolicy::default().check(&email("postmaster@mailinator.com")));
+    }
+
+    #[test]
+    fn only_allowed_domains_and_their_subdomains_are_accepted() {
+        let policy = EmailPolicy::new(domains(&["example.com"]), vec![]);
+
+        assert_ok!(policy.check(&email("ursula@example.com")));
+        assert_ok!(policy.check(&email("ursula@mail.Example.com")));
+        assert_eq!(
+            policy.check(&email("ursula@notexample.com")),
+            Err(EmailError::DomainNotAllowed("notexample.com".into()))
+        );
+    }
+
+    #[test]
+    fn denied_domains_are_rejected() {
+        let policy = EmailPolicy::new(vec![], domains(&["spam.example"]));
+
+        assert_eq!(
+            policy.check(&email("ursula@eu.spam.example")),
+            Err(EmailError::DomainDenied("eu.spam.example".into()))
+        );
+    }
+
+    #[test]
+    fn disposable_domains_are_rejected_unless_explicitly_allowed() {
+        let disposable = domains(&["mailinator.com"]);
+        let policy = EmailPolicy::default().with_disposable_domains(disposable.clone());
+
+        assert_eq!(
+            policy.check(&email("ursula@MAILINATOR.com")),
+            Err(EmailError::DisposableDomain("mailinator.com".into()))
+        );
+
+        let policy = EmailPolicy::new(domains(&["mailinator.com"]), vec![])
+            .with_disposable_domains(disposable);
+        assert_ok!(policy.check(&email("ursula@mailinator.com")));
+    }
+
+    #[test]
+    fn role_accounts_are_rejected_with_or_without_a_tag() {
+        let policy = EmailPolicy::default().with_role_accounts(domains(&["noreply"]));
+
+        assert_eq!(
+            policy.check(&email("NoReply+news@example.com")),
+            Err(EmailError::RoleAccount("noreply".into()))
+        );
+        assert_ok!(policy.check(&email("noreplies@example.com")));
+    }
+
+    #[test]
+    fn domain_lists_skip_comments_and_blank_lines() {
+        let list = parse_domain_list("# disposable\nmailinator.com\n\n  yopmail.com # yes\n");
+
+        assert_eq!(list, domains(&["mailinator.com", "yopmail.com"]));
+    }
+}
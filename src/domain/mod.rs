@@ -1,7 +1,11 @@
+mod email_policy;
 mod new_subscriber;
 mod subscriber_email;
 mod subscriber_name;
+mod subscription_token;
 
+pub use email_policy::*;
 pub use new_subscriber::*;
 pub use subscriber_email::*;
 pub use subscriber_name::*;
+pub use subscription_token::*;
@@ -1,9 +1,19 @@
 use validator::validate_email;
 
-#[derive(thiserror::Error, Debug)]
+/// The address itself is left out of `InvalidEmail`, as error chains are
+/// logged without going through the redaction policy.
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
 pub enum EmailError {
-    #[error("invalid email {0}")]
-    InvalidEmail(String),
+    #[error("invalid email address")]
+    InvalidEmail,
+    #[error("addresses at {0} are not accepted")]
+    DomainNotAllowed(String),
+    #[error("addresses at {0} are blocked")]
+    DomainDenied(String),
+    #[error("{0} is a disposable email provider")]
+    DisposableDomain(String),
+    #[error("{0}@ is a role account, please use a personal address")]
+    RoleAccount(String),
 }
 
 #[derive(Debug)]
@@ -14,9 +24,27 @@ impl SubscriberEmail {
         if validate_email(&s) {
             Ok(Self(s))
         } else {
-            Err(EmailError::InvalidEmail(s))
+            Err(EmailError::InvalidEmail)
         }
     }
+
+    /// The part after the `@`, lowercased.
+    pub fn domain(&self) -> String {
+        self.parts().1.to_lowercase()
+    }
+
+    /// The part before the `@`, lowercased and without any `+tag`.
+    pub fn mailbox(&self) -> String {
+        let local = self.parts().0;
+        local
+            .split_once('+')
+            .map_or(local, |(mailbox, _tag)| mailbox)
+            .to_lowercase()
+    }
+
+    fn parts(&self) -> (&str, &str) {
+        self.0.rsplit_once('@').unwrap_or((&self.0, ""))
+    }
 }
 
 impl AsRef<str> for SubscriberEmail {
@@ -38,6 +66,12 @@ mod tests {
         assert_err!(SubscriberEmail::parse(email));
     }
 
+    #[test]
+    fn rejected_addresses_are_kept_out_of_the_error_message() {
+        let error = SubscriberEmail::parse("ursula@le@guin.com".to_string()).unwrap_err();
+        assert!(!error.to_string().contains("ursula"));
+    }
+
     #[derive(Clone, Debug)]
     struct ValidEmailFixture(pub String);
     impl quickcheck::Arbitrary for ValidEmailFixture {
@@ -4,10 +4,10 @@ use unicode_segmentation::UnicodeSegmentation;
 pub enum NameError {
     #[error("name must not be empty")]
     EmptyOrWhitespace,
-    #[error("name must be less than 256 characters, was {} characters", .0.len())]
-    TooLong(String),
-    #[error("name {0} contains forbidden characters")]
-    ForbiddenCharacters(String),
+    #[error("name must be less than 256 characters, was {0} characters")]
+    TooLong(usize),
+    #[error("name contains forbidden characters")]
+    ForbiddenCharacters,
 }
 
 #[derive(Debug)]
@@ -17,12 +17,13 @@ impl SubscriberName {
     pub fn parse(s: String) -> Result<Self, NameError> {
         let forbidden_characters = ['/', '(', ')', '"', '<', '>', '\\', '{', '}'];
 
-        if s.graphemes(true).count() > 256 {
-            Err(NameError::TooLong(s))
+        let length = s.graphemes(true).count();
+        if length > 256 {
+            Err(NameError::TooLong(length))
         } else if s.trim().is_empty() {
             Err(NameError::EmptyOrWhitespace)
         } else if s.chars().any(|c| forbidden_characters.contains(&c)) {
-            Err(NameError::ForbiddenCharacters(s))
+            Err(NameError::ForbiddenCharacters)
         } else {
             Ok(Self(s))
         }
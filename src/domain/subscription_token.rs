@@ -0,0 +1,58 @@
+use rand::{distributions::Alphanumeric, rngs::OsRng, Rng};
+use sha2::{Digest, Sha256};
+
+const TOKEN_LENGTH: usize = 32;
+
+/// A confirmation token as handed out in emails. Only its hash is stored, so
+/// the plaintext never has to leave this process.
+pub struct SubscriptionToken(String);
+
+impl SubscriptionToken {
+    pub fn generate() -> Self {
+        let token = OsRng
+            .sample_iter(&Alphanumeric)
+            .map(char::from)
+            .take(TOKEN_LENGTH)
+            .collect();
+        Self(token)
+    }
+
+    pub fn hash(&self) -> String {
+        hash_token(&self.0)
+    }
+}
+
+impl AsRef<str> for SubscriptionToken {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+/// Hex-encoded SHA-256 of a token, as stored in `subscription_tokens`.
+pub fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{hash_token, SubscriptionToken};
+
+    #[test]
+    fn generated_tokens_are_url_safe_and_unique() {
+        let a = SubscriptionToken::generate();
+        let b = SubscriptionToken::generate();
+
+        assert_eq!(a.as_ref().len(), 32);
+        assert!(a.as_ref().chars().all(|c| c.is_ascii_alphanumeric()));
+        assert_ne!(a.as_ref(), b.as_ref());
+    }
+
+    #[test]
+    fn hash_does_not_contain_the_token() {
+        let token = SubscriptionToken::generate();
+
+        assert_eq!(token.hash(), hash_token(token.as_ref()));
+        assert_eq!(token.hash().len(), 64);
+        assert!(!token.hash().contains(token.as_ref()));
+    }
+}
@@ -0,0 +1,70 @@
+use std::path::PathBuf;
+
+use lettre::{AsyncFileTransport, AsyncTransport, Tokio1Executor};
+
+use super::{Email, EmailTransport};
+
+/// Writes every email as an `.eml` file into a directory instead of sending it,
+/// for local development.
+pub struct FileTransport {
+    transport: AsyncFileTransport<Tokio1Executor>,
+}
+
+impl FileTransport {
+    pub fn new(directory: PathBuf) -> Result<Self, anyhow::Error> {
+        std::fs::create_dir_all(&directory)?;
+        Ok(Self {
+            transport: AsyncFileTransport::new(directory),
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailTransport for FileTransport {
+    async fn send(&self, email: &Email<'_>) -> Result<(), anyhow::Error> {
+        let id = self.transport.send(email.to_message()?).await?;
+        tracing::info!(email_id = %id, "Wrote email to disk");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claim::assert_ok;
+    use fake::{faker::internet::en::SafeEmail, Fake};
+    use uuid::Uuid;
+
+    use super::FileTransport;
+    use crate::{domain::SubscriberEmail, email_client::EmailClient};
+
+    #[tokio::test]
+    async fn send_email_writes_an_eml_file() {
+        let directory = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        let email_client = EmailClient::new(
+            SubscriberEmail::parse(SafeEmail().fake()).unwrap(),
+            FileTransport::new(directory.clone()).unwrap(),
+        );
+
+        let res = email_client
+            .send_email(
+                SubscriberEmail::parse(SafeEmail().fake()).unwrap(),
+                "A subject",
+                "<p>Some HTML</p>",
+                "Some text",
+            )
+            .await;
+        assert_ok!(res);
+
+        let files = std::fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect::<Vec<_>>();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].extension().unwrap(), "eml");
+        assert!(std::fs::read_to_string(&files[0])
+            .unwrap()
+            .contains("Subject: A subject"));
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
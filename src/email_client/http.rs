@@ -0,0 +1,465 @@
+use std::time::Duration;
+
+use rand::Rng;
+use reqwest::{header::RETRY_AFTER, Client, Response, StatusCode};
+use secrecy::{ExposeSecret, Secret};
+
+use super::{Email, EmailTransport};
+use crate::{metrics::METRICS, telemetry::trace_context_headers};
+
+/// Sends email through a Postmark-style JSON API.
+pub struct HttpTransport {
+    base_url: String,
+    http_client: Client,
+    authorization_token: Secret<String>,
+    retry_policy: RetryPolicy,
+}
+
+/// How often and how patiently a failed request to the email API is retried.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    pub jitter: bool,
+    pub retryable_status_codes: Vec<StatusCode>,
+}
+
+impl RetryPolicy {
+    /// Delay before the retry following the given (zero-based) attempt: the
+    /// base delay doubled per attempt, capped at the maximum and, with jitter,
+    /// drawn at random from its upper half.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let delay = self
+            .base_delay
+            .saturating_mul(2_u32.saturating_pow(attempt))
+            .min(self.max_delay);
+
+        if self.jitter && !delay.is_zero() {
+            rand::thread_rng().gen_range(delay / 2..=delay)
+        } else {
+            delay
+        }
+    }
+
+    fn is_retryable(&self, status: StatusCode) -> bool {
+        self.retryable_status_codes.contains(&status)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(5),
+            jitter: true,
+            retryable_status_codes: vec![
+                StatusCode::TOO_MANY_REQUESTS,
+                StatusCode::INTERNAL_SERVER_ERROR,
+                StatusCode::BAD_GATEWAY,
+                StatusCode::SERVICE_UNAVAILABLE,
+                StatusCode::GATEWAY_TIMEOUT,
+            ],
+        }
+    }
+}
+
+/// Reads a `Retry-After` header given either in seconds or as an HTTP date.
+fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?;
+
+    if let Ok(seconds) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    (date.with_timezone(&chrono::Utc) - chrono::Utc::now())
+        .to_std()
+        .ok()
+}
+
+impl HttpTransport {
+    pub fn new(
+        base_url: String,
+        authorization_token: Secret<String>,
+        timeout: std::time::Duration,
+        retry_policy: RetryPolicy,
+    ) -> Self {
+        HttpTransport {
+            http_client: Client::builder().timeout(timeout).build().unwrap(),
+            base_url,
+            authorization_token,
+            retry_policy,
+        }
+    }
+
+    async fn post_email(&self, email: &Email<'_>) -> Result<(), reqwest::Error> {
+        let url = format!("{}/email", self.base_url);
+        let headers = email.headers();
+        let request_body = SendEmailRequest {
+            from: email.from.as_ref(),
+            to: email.to.as_ref(),
+            subject: email.subject,
+            html_body: email.html_body,
+            text_body: email.text_body,
+            headers: headers
+                .iter()
+                .map(|(name, value)| EmailHeader { name, value })
+                .collect(),
+        };
+
+        let trace_context = trace_context_headers();
+
+        let mut attempt = 0;
+        loop {
+            let is_last_attempt = attempt + 1 >= self.retry_policy.max_attempts;
+            let outcome = trace_context
+                .iter()
+                .fold(self.http_client.post(&url), |request, (name, value)| {
+                    request.header(name, value)
+                })
+                .json(&request_body)
+                .header("Authorization", self.authorization_token.expose_secret())
+                .send()
+                .await;
+
+            let status = match &outcome {
+                Ok(response) => response.status().as_str().to_string(),
+                Err(_) => "error".to_string(),
+            };
+            METRICS
+                .email_api_responses_total
+                .with_label_values(&[&status])
+                .inc();
+
+            let delay = match outcome {
+                Ok(response) if response.status().is_success() => return Ok(()),
+                Ok(response)
+                    if !is_last_attempt && self.retry_policy.is_retryable(response.status()) =>
+                {
+                    let backoff = self.retry_policy.backoff(attempt);
+                    let delay = retry_after(&response)
+                        .map_or(backoff, |d| d.min(self.retry_policy.max_delay));
+                    tracing::warn!(
+                        status = %response.status(),
+                        attempt = attempt + 1,
+                        "The email API returned a retryable error, retrying in {:?}",
+                        delay
+                    );
+                    delay
+                }
+                Ok(response) => {
+                    response.error_for_status()?;
+                    return Ok(());
+                }
+                Err(e) if !is_last_attempt && (e.is_timeout() || e.is_connect()) => {
+                    let delay = self.retry_policy.backoff(attempt);
+                    tracing::warn!(
+                        error.message = %e,
+                        attempt = attempt + 1,
+                        "Failed to reach the email API, retrying in {:?}",
+                        delay
+                    );
+                    delay
+                }
+                Err(e) => return Err(e),
+            };
+
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailTransport for HttpTransport {
+    async fn send(&self, email: &Email<'_>) -> Result<(), anyhow::Error> {
+        Ok(self.post_email(email).await?)
+    }
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct SendEmailRequest<'a> {
+    from: &'a str,
+    to: &'a str,
+    subject: &'a str,
+    html_body: &'a str,
+    text_body: &'a str,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    headers: Vec<EmailHeader<'a>>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct EmailHeader<'a> {
+    name: &'a str,
+    value: &'a str,
+}
+
+#[cfg(test)]
+mod tests {
+    use claim::{assert_err, assert_ok};
+    use fake::{
+        faker::{
+            internet::en::SafeEmail,
+            lorem::en::{Paragraph, Sentence},
+        },
+        Fake, Faker,
+    };
+    use secrecy::Secret;
+    use wiremock::{
+        matchers::{any, header, header_exists, method, path},
+        Mock, MockServer, Request, ResponseTemplate,
+    };
+
+    use crate::{
+        domain::SubscriberEmail,
+        email_client::{EmailClient, HttpTransport, RetryPolicy},
+    };
+
+    struct SendEmailBodyMatcher;
+
+    impl wiremock::Match for SendEmailBodyMatcher {
+        fn matches(&self, request: &Request) -> bool {
+            match request.body_json::<serde_json::Value>() {
+                Ok(body) => {
+                    body.get("From").is_some()
+                        && body.get("To").is_some()
+                        && body.get("Subject").is_some()
+                        && body.get("HtmlBody").is_some()
+                        && body.get("TextBody").is_some()
+                }
+                Err(_) => false,
+            }
+        }
+    }
+
+    fn email() -> SubscriberEmail {
+        SubscriberEmail::parse(SafeEmail().fake()).unwrap()
+    }
+
+    fn retry_policy() -> RetryPolicy {
+        RetryPolicy {
+            base_delay: std::time::Duration::from_millis(1),
+            max_delay: std::time::Duration::from_millis(10),
+            ..RetryPolicy::default()
+        }
+    }
+
+    fn email_client(uri: String) -> EmailClient {
+        let transport = HttpTransport::new(
+            uri,
+            Secret::new(Faker.fake()),
+            std::time::Duration::from_millis(10),
+            retry_policy(),
+        );
+        EmailClient::new(email(), transport)
+    }
+
+    fn subject() -> String {
+        Sentence(1..2).fake()
+    }
+
+    fn content() -> String {
+        Paragraph(1..10).fake()
+    }
+
+    #[tokio::test]
+    async fn send_email_sends_expected_request() {
+        let server = MockServer::start().await;
+        let email_client = email_client(server.uri());
+
+        Mock::given(header_exists("Authorization"))
+            .and(header("Content-Type", "application/json"))
+            .and(path("/email"))
+            .and(method("POST"))
+            .and(SendEmailBodyMatcher)
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let res = email_client
+            .send_email(email(), &subject(), &content(), &content())
+            .await;
+
+        assert_ok!(res);
+    }
+
+    #[tokio::test]
+    async fn send_email_errors_on_server_error() {
+        let server = MockServer::start().await;
+        let email_client = email_client(server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(3)
+            .mount(&server)
+            .await;
+
+        let res = email_client
+            .send_email(email(), &subject(), &content(), &content())
+            .await;
+
+        assert_err!(res);
+    }
+
+    #[tokio::test]
+    async fn send_email_times_out() {
+        let server = MockServer::start().await;
+        let email_client = email_client(server.uri());
+
+        let response = ResponseTemplate::new(200).set_delay(std::time::Duration::from_secs(180));
+
+        Mock::given(any())
+            .respond_with(response)
+            .expect(3)
+            .mount(&server)
+            .await;
+
+        let res = email_client
+            .send_email(email(), &subject(), &content(), &content())
+            .await;
+
+        assert_err!(res);
+    }
+
+    #[tokio::test]
+    async fn send_email_retries_until_success() {
+        let server = MockServer::start().await;
+        let email_client = email_client(server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(2)
+            .expect(2)
+            .mount(&server)
+            .await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let res = email_client
+            .send_email(email(), &subject(), &content(), &content())
+            .await;
+
+        assert_ok!(res);
+    }
+
+    #[tokio::test]
+    async fn send_email_does_not_retry_client_errors() {
+        let server = MockServer::start().await;
+        let email_client = email_client(server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(422))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let res = email_client
+            .send_email(email(), &subject(), &content(), &content())
+            .await;
+
+        assert_err!(res);
+    }
+
+    #[tokio::test]
+    async fn send_email_honors_retry_after() {
+        let server = MockServer::start().await;
+        let transport = HttpTransport::new(
+            server.uri(),
+            Secret::new(Faker.fake()),
+            std::time::Duration::from_millis(100),
+            RetryPolicy {
+                max_delay: std::time::Duration::from_secs(5),
+                ..retry_policy()
+            },
+        );
+        let email_client = EmailClient::new(email(), transport);
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "1"))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&server)
+            .await;
+
+        let start = std::time::Instant::now();
+        let res = email_client
+            .send_email(email(), &subject(), &content(), &content())
+            .await;
+
+        assert_ok!(res);
+        assert!(start.elapsed() >= std::time::Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn send_list_email_sends_unsubscribe_headers() {
+        let server = MockServer::start().await;
+        let email_client = email_client(server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let res = email_client
+            .send_list_email(
+                email(),
+                &subject(),
+                &content(),
+                &content(),
+                "https://example.com/unsubscribe?token=abc",
+            )
+            .await;
+        assert_ok!(res);
+
+        let request = &server.received_requests().await.unwrap()[0];
+        let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+        assert_eq!(
+            body["Headers"],
+            serde_json::json!([
+                {"Name": "List-Unsubscribe", "Value": "<https://example.com/unsubscribe?token=abc>"},
+                {"Name": "List-Unsubscribe-Post", "Value": "List-Unsubscribe=One-Click"},
+            ])
+        );
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_maximum_delay() {
+        let policy = RetryPolicy {
+            base_delay: std::time::Duration::from_millis(100),
+            max_delay: std::time::Duration::from_millis(350),
+            jitter: false,
+            ..RetryPolicy::default()
+        };
+
+        assert_eq!(policy.backoff(0), std::time::Duration::from_millis(100));
+        assert_eq!(policy.backoff(1), std::time::Duration::from_millis(200));
+        assert_eq!(policy.backoff(2), std::time::Duration::from_millis(350));
+        assert_eq!(policy.backoff(40), std::time::Duration::from_millis(350));
+    }
+
+    #[test]
+    fn jittered_backoff_stays_within_bounds() {
+        let policy = RetryPolicy {
+            base_delay: std::time::Duration::from_millis(100),
+            ..RetryPolicy::default()
+        };
+
+        for _ in 0..100 {
+            let delay = policy.backoff(1);
+            assert!(delay >= std::time::Duration::from_millis(100));
+            assert!(delay <= std::time::Duration::from_millis(200));
+        }
+    }
+}
@@ -0,0 +1,190 @@
+mod file;
+mod http;
+mod smtp;
+
+use std::sync::Arc;
+
+use lettre::{
+    message::{
+        header::{Header, HeaderName, HeaderValue},
+        MultiPart,
+    },
+    Message,
+};
+
+pub use file::*;
+pub use http::*;
+pub use smtp::*;
+
+use crate::{domain::SubscriberEmail, metrics::METRICS};
+
+/// A single outgoing email, independent of how it is delivered.
+pub struct Email<'a> {
+    pub from: &'a SubscriberEmail,
+    pub to: &'a SubscriberEmail,
+    pub subject: &'a str,
+    pub html_body: &'a str,
+    pub text_body: &'a str,
+    /// One-click unsubscribe URL advertised through the `List-Unsubscribe`
+    /// and `List-Unsubscribe-Post` headers (RFC 8058).
+    pub list_unsubscribe_url: Option<&'a str>,
+}
+
+impl Email<'_> {
+    /// Extra headers to set on the message, on top of the usual envelope.
+    pub fn headers(&self) -> Vec<(&'static str, String)> {
+        match self.list_unsubscribe_url {
+            Some(url) => vec![
+                (ListUnsubscribe::NAME, format!("<{}>", url)),
+                (
+                    ListUnsubscribePost::NAME,
+                    ListUnsubscribePost::VALUE.to_string(),
+                ),
+            ],
+            None => vec![],
+        }
+    }
+
+    /// Renders the email as an RFC 5322 message with plain text and HTML
+    /// alternatives.
+    pub fn to_message(&self) -> Result<Message, anyhow::Error> {
+        let mut builder = Message::builder()
+            .from(self.from.as_ref().parse()?)
+            .to(self.to.as_ref().parse()?)
+            .subject(self.subject);
+
+        if let Some(url) = self.list_unsubscribe_url {
+            builder = builder
+                .header(ListUnsubscribe(format!("<{}>", url)))
+                .header(ListUnsubscribePost);
+        }
+
+        let message = builder.multipart(MultiPart::alternative_plain_html(
+            self.text_body.to_string(),
+            self.html_body.to_string(),
+        ))?;
+
+        Ok(message)
+    }
+}
+
+#[derive(Clone)]
+struct ListUnsubscribe(String);
+
+impl ListUnsubscribe {
+    const NAME: &'static str = "List-Unsubscribe";
+}
+
+impl Header for ListUnsubscribe {
+    fn name() -> HeaderName {
+        HeaderName::new_from_ascii_str(Self::NAME)
+    }
+
+    fn parse(s: &str) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(Self(s.to_string()))
+    }
+
+    fn display(&self) -> HeaderValue {
+        HeaderValue::new(Self::name(), self.0.clone())
+    }
+}
+
+#[derive(Clone)]
+struct ListUnsubscribePost;
+
+impl ListUnsubscribePost {
+    const NAME: &'static str = "List-Unsubscribe-Post";
+    const VALUE: &'static str = "List-Unsubscribe=One-Click";
+}
+
+impl Header for ListUnsubscribePost {
+    fn name() -> HeaderName {
+        HeaderName::new_from_ascii_str(Self::NAME)
+    }
+
+    fn parse(_: &str) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(Self)
+    }
+
+    fn display(&self) -> HeaderValue {
+        HeaderValue::new(Self::name(), Self::VALUE.to_string())
+    }
+}
+
+/// Delivers emails to their recipients.
+#[async_trait::async_trait]
+pub trait EmailTransport: Send + Sync {
+    async fn send(&self, email: &Email<'_>) -> Result<(), anyhow::Error>;
+}
+
+/// Sends email on behalf of the configured sender through whichever transport
+/// this environment uses.
+#[derive(Clone)]
+pub struct EmailClient {
+    sender: Arc<SubscriberEmail>,
+    transport: Arc<dyn EmailTransport>,
+}
+
+impl EmailClient {
+    pub fn new(sender: SubscriberEmail, transport: impl EmailTransport + 'static) -> Self {
+        Self {
+            sender: Arc::new(sender),
+            transport: Arc::new(transport),
+        }
+    }
+
+    pub async fn send_email(
+        &self,
+        recipient: SubscriberEmail,
+        subject: &str,
+        html_body: &str,
+        text_body: &str,
+    ) -> Result<(), anyhow::Error> {
+        let email = Email {
+            from: &self.sender,
+            to: &recipient,
+            subject,
+            html_body,
+            text_body,
+            list_unsubscribe_url: None,
+        };
+
+        self.deliver(&email).await
+    }
+
+    /// Sends a mailing list email that recipients can unsubscribe from with a
+    /// single click.
+    pub async fn send_list_email(
+        &self,
+        recipient: SubscriberEmail,
+        subject: &str,
+        html_body: &str,
+        text_body: &str,
+        unsubscribe_url: &str,
+    ) -> Result<(), anyhow::Error> {
+        let email = Email {
+            from: &self.sender,
+            to: &recipient,
+            subject,
+            html_body,
+            text_body,
+            list_unsubscribe_url: Some(unsubscribe_url),
+        };
+
+        self.deliver(&email).await
+    }
+
+    async fn deliver(&self, email: &Email<'_>) -> Result<(), anyhow::Error> {
+        let started = std::time::Instant::now();
+        let outcome = self.transport.send(email).await;
+        METRICS
+            .email_send_duration_seconds
+            .with_label_values(&[if outcome.is_ok() {
+                "success"
+            } else {
+                "failure"
+            }])
+            .observe(started.elapsed().as_secs_f64());
+        outcome
+    }
+}
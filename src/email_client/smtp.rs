@@ -0,0 +1,228 @@
+use std::time::Duration;
+
+use lettre::{
+    transport::smtp::authentication::Credentials, AsyncSmtpTransport, AsyncTransport,
+    Tokio1Executor,
+};
+use secrecy::{ExposeSecret, Secret};
+
+use super::{Email, EmailTransport};
+
+#[derive(serde::Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpTls {
+    /// Upgrade a plaintext connection with STARTTLS, refusing to continue
+    /// without it.
+    #[default]
+    Starttls,
+    /// Connect over TLS from the start (usually port 465).
+    Tls,
+    /// Never encrypt. Only meant for local test servers.
+    None,
+}
+
+/// Sends email through an SMTP relay.
+pub struct SmtpTransport {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+}
+
+impl SmtpTransport {
+    pub fn new(
+        host: &str,
+        port: u16,
+        tls: SmtpTls,
+        credentials: Option<(String, Secret<String>)>,
+        timeout: Duration,
+    ) -> Result<Self, anyhow::Error> {
+        let builder = match tls {
+            SmtpTls::Starttls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)?,
+            SmtpTls::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(host)?,
+            SmtpTls::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host),
+        };
+
+        let mut builder = builder.port(port).timeout(Some(timeout));
+        if let Some((username, password)) = credentials {
+            builder = builder.credentials(Credentials::new(
+                username,
+                password.expose_secret().to_owned(),
+            ));
+        }
+
+        Ok(Self {
+            transport: builder.build(),
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailTransport for SmtpTransport {
+    async fn send(&self, email: &Email<'_>) -> Result<(), anyhow::Error> {
+        self.transport.send(email.to_message()?).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::{TcpListener, TcpStream},
+        thread::{self, JoinHandle},
+        time::Duration,
+    };
+
+    use claim::{assert_err, assert_ok};
+    use secrecy::Secret;
+
+    use super::{SmtpTls, SmtpTransport};
+    use crate::{
+        domain::SubscriberEmail,
+        email_client::{Email, EmailTransport},
+    };
+
+    /// Accepts a single connection on a local port and hands it to `serve`,
+    /// returning whatever `serve` collected.
+    fn fake_server<T: Send + 'static>(
+        serve: impl FnOnce(TcpStream) -> T + Send + 'static,
+    ) -> (u16, JoinHandle<T>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let handle = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            stream
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            serve(stream)
+        });
+        (port, handle)
+    }
+
+    /// Plays a plaintext SMTP server advertising `extensions` and returns
+    /// every line the client sent.
+    fn smtp_server(extensions: &'static [&'static str]) -> (u16, JoinHandle<Vec<String>>) {
+        fake_server(move |stream| {
+            let mut writer = stream.try_clone().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut transcript = Vec::new();
+            let mut in_data = false;
+            writer.write_all(b"220 localhost ESMTP\r\n").unwrap();
+
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap_or(0) == 0 {
+                    break;
+                }
+                let line = line.trim_end_matches("\r\n").to_string();
+                transcript.push(line.clone());
+
+                let reply = if in_data {
+                    if line != "." {
+                        continue;
+                    }
+                    in_data = false;
+                    "250 queued\r\n".to_string()
+                } else if line.starts_with("EHLO") {
+                    let mut reply = "250-localhost\r\n".to_string();
+                    for extension in extensions {
+                        reply.push_str(&format!("250-{}\r\n", extension));
+                    }
+                    reply.push_str("250 8BITMIME\r\n");
+                    reply
+                } else if line.starts_with("AUTH") {
+                    "235 authenticated\r\n".to_string()
+                } else if line == "DATA" {
+                    in_data = true;
+                    "354 go ahead\r\n".to_string()
+                } else if line == "QUIT" {
+                    writer.write_all(b"221 bye\r\n").unwrap();
+                    break;
+                } else {
+                    "250 ok\r\n".to_string()
+                };
+                writer.write_all(reply.as_bytes()).unwrap();
+            }
+            transcript
+        })
+    }
+
+    async fn send(transport: &SmtpTransport) -> Result<(), anyhow::Error> {
+        let from = SubscriberEmail::parse("sender@example.com".into()).unwrap();
+        let to = SubscriberEmail::parse("reader@example.com".into()).unwrap();
+        let email = Email {
+            from: &from,
+            to: &to,
+            subject: "A subject",
+            html_body: "<p>Some HTML</p>",
+            text_body: "Some text",
+            list_unsubscribe_url: Some("https://example.com/unsubscribe"),
+        };
+        transport.send(&email).await
+    }
+
+    fn transport(port: u16, tls: SmtpTls) -> SmtpTransport {
+        SmtpTransport::new(
+            "localhost",
+            port,
+            tls,
+            Some(("mailer".into(), Secret::new("password".into()))),
+            Duration::from_secs(5),
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn plaintext_transport_delivers_a_multipart_message() {
+        let (port, server) = smtp_server(&["AUTH PLAIN LOGIN"]);
+
+        assert_ok!(send(&transport(port, SmtpTls::None)).await);
+
+        let transcript = server.join().unwrap();
+        let data = transcript.join("\n");
+        // "\0mailer\0password", base64 encoded.
+        assert!(
+            data.contains("AUTH PLAIN AG1haWxlcgBwYXNzd29yZA=="),
+            "{}",
+            data
+        );
+        assert!(data.contains("MAIL FROM:<sender@example.com>"), "{}", data);
+        assert!(data.contains("RCPT TO:<reader@example.com>"), "{}", data);
+        assert!(data.contains("Subject: A subject"), "{}", data);
+        assert!(data.contains("multipart/alternative"), "{}", data);
+        assert!(data.contains("Some text"), "{}", data);
+        assert!(data.contains("<p>Some HTML</p>"), "{}", data);
+        assert!(
+            data.contains("List-Unsubscribe: <https://example.com/unsubscribe>"),
+            "{}",
+            data
+        );
+    }
+
+    #[tokio::test]
+    async fn starttls_transport_refuses_servers_without_starttls() {
+        let (port, server) = smtp_server(&["AUTH PLAIN LOGIN"]);
+
+        assert_err!(send(&transport(port, SmtpTls::Starttls)).await);
+
+        let transcript = server.join().unwrap();
+        assert!(
+            transcript
+                .iter()
+                .all(|line| !line.starts_with("AUTH") && !line.starts_with("MAIL FROM")),
+            "{:?}",
+            transcript
+        );
+    }
+
+    #[tokio::test]
+    async fn tls_transport_starts_with_a_tls_handshake() {
+        let (port, server) = fake_server(|mut stream| {
+            let mut first = [0u8; 1];
+            stream.read_exact(&mut first).map(|_| first[0])
+        });
+
+        assert_err!(send(&transport(port, SmtpTls::Tls)).await);
+
+        // 0x16 is the record type of a TLS handshake, i.e. a ClientHello.
+        assert_eq!(server.join().unwrap().unwrap(), 0x16);
+    }
+}
@@ -0,0 +1,144 @@
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use sqlx::PgPool;
+
+/// Dependencies checked by `/health/ready` and how long each check may take.
+pub struct ReadinessProbe {
+    database_timeout: Duration,
+    email: Option<EmailProbe>,
+}
+
+struct EmailProbe {
+    base_url: String,
+    http_client: reqwest::Client,
+}
+
+impl ReadinessProbe {
+    pub fn new(database_timeout: Duration) -> Self {
+        Self {
+            database_timeout,
+            email: None,
+        }
+    }
+
+    /// Also require the email provider at `base_url` to answer, with any
+    /// status, within `timeout`.
+    pub fn with_email_probe(mut self, base_url: String, timeout: Duration) -> Self {
+        let http_client = reqwest::Client::builder()
+            .timeout(timeout)
+            .build()
+            .expect("Failed to build the email probe HTTP client");
+        self.email = Some(EmailProbe {
+            base_url,
+            http_client,
+        });
+        self
+    }
+
+    /// Checks every dependency at once. Why a check failed is logged rather
+    /// than reported, since the report is served to anyone who asks.
+    pub async fn check(&self, db_pool: &PgPool) -> Readiness {
+        let (database, email) =
+            tokio::join!(check_database(db_pool, self.database_timeout), async {
+                match &self.email {
+                    Some(email) => Some(check_email_provider(email).await),
+                    None => None,
+                }
+            });
+
+        let healthy = database.check.status == Status::Up
+            && email.as_ref().is_none_or(|e| e.status == Status::Up);
+        Readiness {
+            status: if healthy { Status::Up } else { Status::Down },
+            components: Components { database, email },
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Up,
+    Down,
+}
+
+#[derive(Serialize)]
+pub struct Readiness {
+    pub status: Status,
+    components: Components,
+}
+
+#[derive(Serialize)]
+struct Components {
+    database: DatabaseHealth,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    email: Option<ComponentHealth>,
+}
+
+#[derive(Serialize)]
+struct ComponentHealth {
+    status: Status,
+    latency_ms: u128,
+}
+
+#[derive(Serialize)]
+struct DatabaseHealth {
+    #[serde(flatten)]
+    check: ComponentHealth,
+    pool: PoolStatistics,
+}
+
+#[derive(Serialize)]
+struct PoolStatistics {
+    size: u32,
+    idle: usize,
+}
+
+async fn check_database(db_pool: &PgPool, timeout: Duration) -> DatabaseHealth {
+    let started = Instant::now();
+    let outcome = tokio::time::timeout(timeout, sqlx::query("SELECT 1").execute(db_pool)).await;
+    let up = match outcome {
+        Ok(Ok(_)) => true,
+        Ok(Err(e)) => {
+            tracing::warn!(error.message = %e, "The database readiness check failed");
+            false
+        }
+        Err(_) => {
+            tracing::warn!("The database readiness check timed out after {:?}", timeout);
+            false
+        }
+    };
+
+    DatabaseHealth {
+        check: ComponentHealth::new(started, up),
+        pool: PoolStatistics {
+            size: db_pool.size(),
+            idle: db_pool.num_idle(),
+        },
+    }
+}
+
+async fn check_email_provider(probe: &EmailProbe) -> ComponentHealth {
+    let started = Instant::now();
+    // Any HTTP response shows the provider is reachable; only transport
+    // failures and timeouts count as down.
+    let up = match probe.http_client.get(&probe.base_url).send().await {
+        Ok(_) => true,
+        Err(e) => {
+            tracing::warn!(error.message = %e, "The email provider readiness check failed");
+            false
+        }
+    };
+
+    ComponentHealth::new(started, up)
+}
+
+impl ComponentHealth {
+    fn new(started: Instant, up: bool) -> Self {
+        Self {
+            status: if up { Status::Up } else { Status::Down },
+            latency_ms: started.elapsed().as_millis(),
+        }
+    }
+}
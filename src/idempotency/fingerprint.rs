@@ -0,0 +1,56 @@
+use actix_web::HttpRequest;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+/// A hash of the method, path and payload of a request, stored with its
+/// idempotency key so the key can't be reused for a different request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestFingerprint(Vec<u8>);
+
+impl RequestFingerprint {
+    /// The payload is hashed once deserialized, so the same data sent as JSON
+    /// or as a form gets the same fingerprint.
+    pub fn new(request: &HttpRequest, payload: &impl Serialize) -> Result<Self, serde_json::Error> {
+        let mut hasher = Sha256::new();
+        hasher.update(request.method().as_str());
+        hasher.update(b" ");
+        hasher.update(request.path());
+        hasher.update(b"\n");
+        hasher.update(serde_json::to_vec(payload)?);
+        Ok(Self(hasher.finalize().to_vec()))
+    }
+}
+
+impl AsRef<[u8]> for RequestFingerprint {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+
+    use super::RequestFingerprint;
+
+    #[test]
+    fn fingerprints_differ_by_path_and_payload() {
+        let request = TestRequest::post().uri("/subscriptions").to_http_request();
+        let other_path = TestRequest::post().uri("/newsletters").to_http_request();
+
+        let fingerprint = RequestFingerprint::new(&request, &("ursula", 1)).unwrap();
+
+        assert_eq!(
+            fingerprint,
+            RequestFingerprint::new(&request, &("ursula", 1)).unwrap()
+        );
+        assert_ne!(
+            fingerprint,
+            RequestFingerprint::new(&request, &("ursula", 2)).unwrap()
+        );
+        assert_ne!(
+            fingerprint,
+            RequestFingerprint::new(&other_path, &("ursula", 1)).unwrap()
+        );
+    }
+}
@@ -0,0 +1,82 @@
+use actix_web::http::header::HeaderMap;
+
+pub const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+
+#[derive(thiserror::Error, Debug)]
+pub enum IdempotencyKeyError {
+    #[error("the idempotency key must not be empty")]
+    Empty,
+    #[error(
+        "the idempotency key must be shorter than {} characters",
+        IdempotencyKey::MAX_LENGTH
+    )]
+    TooLong,
+    #[error("the idempotency key must be a valid UTF8 string")]
+    NotUtf8,
+}
+
+#[derive(Debug)]
+pub struct IdempotencyKey(String);
+
+impl IdempotencyKey {
+    const MAX_LENGTH: usize = 50;
+
+    /// Reads the key from the `Idempotency-Key` header, if the client sent one.
+    pub fn from_headers(headers: &HeaderMap) -> Result<Option<Self>, IdempotencyKeyError> {
+        headers
+            .get(IDEMPOTENCY_KEY_HEADER)
+            .map(|value| {
+                let value = value.to_str().map_err(|_| IdempotencyKeyError::NotUtf8)?;
+                value.to_string().try_into()
+            })
+            .transpose()
+    }
+}
+
+impl TryFrom<String> for IdempotencyKey {
+    type Error = IdempotencyKeyError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        if s.is_empty() {
+            Err(IdempotencyKeyError::Empty)
+        } else if s.len() >= Self::MAX_LENGTH {
+            Err(IdempotencyKeyError::TooLong)
+        } else {
+            Ok(Self(s))
+        }
+    }
+}
+
+impl From<IdempotencyKey> for String {
+    fn from(k: IdempotencyKey) -> Self {
+        k.0
+    }
+}
+
+impl AsRef<str> for IdempotencyKey {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claim::{assert_err, assert_ok};
+
+    use super::IdempotencyKey;
+
+    #[test]
+    fn empty_key_is_rejected() {
+        assert_err!(IdempotencyKey::try_from("".to_string()));
+    }
+
+    #[test]
+    fn long_key_is_rejected() {
+        assert_err!(IdempotencyKey::try_from("a".repeat(50)));
+    }
+
+    #[test]
+    fn valid_key() {
+        assert_ok!(IdempotencyKey::try_from("a".repeat(49)));
+    }
+}
@@ -0,0 +1,265 @@
+use std::{
+    net::IpAddr,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use actix_web::{body::to_bytes, http::StatusCode, HttpResponse};
+use chrono::Utc;
+use sha2::{Digest, Sha256};
+use sqlx::{postgres::PgHasArrayType, PgPool, Postgres, Transaction};
+use uuid::{Builder, Uuid};
+
+use super::{IdempotencyKey, RequestFingerprint};
+use crate::routes::ProblemDetails;
+
+/// Owner of idempotency keys sent to endpoints that don't require a login,
+/// when the client's address is unknown.
+pub const ANONYMOUS_USER_ID: Uuid = Uuid::nil();
+
+/// Owner of idempotency keys sent by `client` to endpoints that don't require
+/// a login, so that anonymous clients can't claim or replay each other's keys.
+pub fn anonymous_user_id(client: IpAddr) -> Uuid {
+    let digest = Sha256::digest(format!("anonymous:{}", client).as_bytes());
+    let mut bytes = [0u8; 16];
+    bytes.copy_from_slice(&digest[..16]);
+    Builder::from_bytes(bytes).into_uuid()
+}
+
+/// How long a key, and the response saved for it, is kept.
+const KEY_TTL_HOURS: i64 = 24;
+
+/// Expired keys are deleted once every this many claims.
+const PRUNE_EVERY: u64 = 1_000;
+
+static CLAIMS: AtomicU64 = AtomicU64::new(0);
+
+#[tracing::instrument(name = "Pruning expired idempotency keys", skip(db_pool))]
+async fn prune_expired_keys(db_pool: &PgPool) -> Result<(), sqlx::Error> {
+    sqlx::query!("DELETE FROM idempotency WHERE expires_at <= now()")
+        .execute(db_pool)
+        .await?;
+    Ok(())
+}
+
+#[derive(Debug, sqlx::Type)]
+#[sqlx(type_name = "header_pair")]
+struct HeaderPairRecord {
+    name: String,
+    value: Vec<u8>,
+}
+
+impl PgHasArrayType for HeaderPairRecord {
+    fn array_type_info() -> sqlx::postgres::PgTypeInfo {
+        sqlx::postgres::PgTypeInfo::with_name("_header_pair")
+    }
+}
+
+#[allow(clippy::large_enum_variant)]
+pub enum NextAction {
+    StartProcessing(Transaction<'static, Postgres>),
+    ReturnSavedResponse(HttpResponse),
+}
+
+/// Claims the key for the current request, or hands back the response that was
+/// stored for it. A concurrent request holding the same key blocks the insert
+/// until its transaction finishes, so the request is only ever executed once.
+/// A key first used for a different request gets a 422 instead. Expired keys
+/// are claimed afresh.
+#[tracing::instrument(
+    name = "Claiming an idempotency key",
+    skip(db_pool, idempotency_key, fingerprint)
+)]
+pub async fn try_processing(
+    db_pool: &PgPool,
+    idempotency_key: &IdempotencyKey,
+    user_id: Uuid,
+    fingerprint: &RequestFingerprint,
+) -> Result<NextAction, anyhow::Error> {
+    if CLAIMS
+        .fetch_add(1, Ordering::Relaxed)
+        .is_multiple_of(PRUNE_EVERY)
+    {
+        prune_expired_keys(db_pool).await?;
+    }
+
+    let mut txn = db_pool.begin().await?;
+
+    let now = Utc::now();
+    let n_inserted_rows = sqlx::query!(
+        r#"
+        INSERT INTO idempotency (user_id, idempotency_key, request_hash, created_at, expires_at)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (user_id, idempotency_key) DO UPDATE
+        SET
+            request_hash = EXCLUDED.request_hash,
+            created_at = EXCLUDED.created_at,
+            expires_at = EXCLUDED.expires_at,
+            response_status_code = NULL,
+            response_headers = NULL,
+            response_body = NULL
+        WHERE idempotency.expires_at <= $4
+        "#,
+        user_id,
+        idempotency_key.as_ref(),
+        fingerprint.as_ref(),
+        now,
+        now + chrono::Duration::hours(KEY_TTL_HOURS),
+    )
+    .execute(&mut txn)
+    .await?
+    .rows_affected();
+
+    if n_inserted_rows > 0 {
+        Ok(NextAction::StartProcessing(txn))
+    } else if is_reused_for_another_request(db_pool, idempotency_key, user_id, fingerprint).await? {
+        Ok(NextAction::ReturnSavedResponse(
+            ProblemDetails::new(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "This idempotency key was already used for a different request.",
+            )
+            .into_response(),
+        ))
+    } else {
+        let saved_response = get_saved_response(db_pool, idempotency_key, user_id)
+            .await?
+            .unwrap_or_else(|| {
+                HttpResponse::Conflict()
+                    .body("A request with this idempotency key is still being processed")
+            });
+        Ok(NextAction::ReturnSavedResponse(saved_response))
+    }
+}
+
+async fn is_reused_for_another_request(
+    db_pool: &PgPool,
+    idempotency_key: &IdempotencyKey,
+    user_id: Uuid,
+    fingerprint: &RequestFingerprint,
+) -> Result<bool, anyhow::Error> {
+    let saved = sqlx::query!(
+        r#"
+        SELECT request_hash
+        FROM idempotency
+        WHERE user_id = $1 AND idempotency_key = $2
+        "#,
+        user_id,
+        idempotency_key.as_ref(),
+    )
+    .fetch_optional(db_pool)
+    .await?;
+
+    Ok(saved
+        .and_then(|r| r.request_hash)
+        .is_some_and(|hash| hash != fingerprint.as_ref()))
+}
+
+#[tracing::instrument(name = "Fetching a saved response", skip(db_pool, idempotency_key))]
+pub async fn get_saved_response(
+    db_pool: &PgPool,
+    idempotency_key: &IdempotencyKey,
+    user_id: Uuid,
+) -> Result<Option<HttpResponse>, anyhow::Error> {
+    let saved_response = sqlx::query!(
+        r#"
+        SELECT
+            response_status_code as "response_status_code?",
+            response_headers as "response_headers?: Vec<HeaderPairRecord>",
+            response_body as "response_body?"
+        FROM idempotency
+        WHERE user_id = $1 AND idempotency_key = $2
+        "#,
+        user_id,
+        idempotency_key.as_ref(),
+    )
+    .fetch_optional(db_pool)
+    .await?;
+
+    let r = match saved_response {
+        Some(r) => r,
+        None => return Ok(None),
+    };
+
+    match (r.response_status_code, r.response_headers, r.response_body) {
+        (Some(status_code), Some(headers), Some(body)) => {
+            let status_code = StatusCode::from_u16(status_code.try_into()?)?;
+            let mut response = HttpResponse::build(status_code);
+            for HeaderPairRecord { name, value } in headers {
+                response.append_header((name, value));
+            }
+            Ok(Some(response.body(body)))
+        }
+        _ => Ok(None),
+    }
+}
+
+#[tracing::instrument(name = "Saving a response", skip(txn, idempotency_key, http_response))]
+pub async fn save_response(
+    mut txn: Transaction<'static, Postgres>,
+    idempotency_key: &IdempotencyKey,
+    user_id: Uuid,
+    http_response: HttpResponse,
+) -> Result<HttpResponse, anyhow::Error> {
+    let (response_head, body) = http_response.into_parts();
+    let body = to_bytes(body).await.map_err(|e| anyhow::anyhow!("{}", e))?;
+    let status_code = response_head.status().as_u16() as i16;
+    let headers = response_head
+        .headers()
+        .iter()
+        .map(|(name, value)| HeaderPairRecord {
+            name: name.as_str().to_owned(),
+            value: value.as_bytes().to_owned(),
+        })
+        .collect::<Vec<_>>();
+
+    sqlx::query_unchecked!(
+        r#"
+        UPDATE idempotency
+        SET
+            response_status_code = $3,
+            response_headers = $4,
+            response_body = $5
+        WHERE user_id = $1 AND idempotency_key = $2
+        "#,
+        user_id,
+        idempotency_key.as_ref(),
+        status_code,
+        headers,
+        body.as_ref(),
+    )
+    .execute(&mut txn)
+    .await?;
+    txn.commit().await?;
+
+    Ok(response_head.set_body(body).map_into_boxed_body())
+}
+
+/// Starts the transaction a request runs in, claiming the idempotency key
+/// first when the client sent one.
+pub async fn begin_request(
+    db_pool: &PgPool,
+    idempotency_key: Option<&IdempotencyKey>,
+    user_id: Uuid,
+    fingerprint: &RequestFingerprint,
+) -> Result<NextAction, anyhow::Error> {
+    match idempotency_key {
+        Some(key) => try_processing(db_pool, key, user_id, fingerprint).await,
+        None => Ok(NextAction::StartProcessing(db_pool.begin().await?)),
+    }
+}
+
+/// Commits the request's transaction, storing the response for replay when the
+/// client sent an idempotency key.
+pub async fn complete_request(
+    txn: Transaction<'static, Postgres>,
+    idempotency_key: Option<&IdempotencyKey>,
+    user_id: Uuid,
+    http_response: HttpResponse,
+) -> Result<HttpResponse, anyhow::Error> {
+    match idempotency_key {
+        Some(key) => save_response(txn, key, user_id, http_response).await,
+        None => {
+            txn.commit().await?;
+            Ok(http_response)
+        }
+    }
+}
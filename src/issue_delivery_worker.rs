@@ -0,0 +1,243 @@
+use std::time::Duration;
+
+use sqlx::{PgPool, Postgres, Transaction};
+use tracing::{field::display, Span};
+use uuid::Uuid;
+
+use crate::{domain::SubscriberEmail, email_client::EmailClient, unsubscribe::UnsubscribeLinks};
+
+// Failed deliveries are retried with a doubling delay until this many attempts
+// have been made, after which the task is dropped.
+const MAX_RETRIES: i16 = 5;
+const BASE_RETRY_DELAY_SECONDS: i64 = 30;
+
+pub enum ExecutionOutcome {
+    TaskCompleted,
+    EmptyQueue,
+}
+
+struct Task {
+    newsletter_issue_id: Uuid,
+    subscriber_email: String,
+    n_retries: i16,
+}
+
+struct NewsletterIssue {
+    title: String,
+    text_content: String,
+    html_content: String,
+}
+
+pub async fn run_worker_until_stopped(
+    db_pool: PgPool,
+    email_client: EmailClient,
+    unsubscribe_links: UnsubscribeLinks,
+) -> Result<(), anyhow::Error> {
+    worker_loop(db_pool, email_client, unsubscribe_links).await
+}
+
+async fn worker_loop(
+    db_pool: PgPool,
+    email_client: EmailClient,
+    unsubscribe_links: UnsubscribeLinks,
+) -> Result<(), anyhow::Error> {
+    loop {
+        match try_execute_task(&db_pool, &email_client, &unsubscribe_links).await {
+            Ok(ExecutionOutcome::EmptyQueue) => {
+                tokio::time::sleep(Duration::from_secs(10)).await;
+            }
+            Err(_) => {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+            Ok(ExecutionOutcome::TaskCompleted) => {}
+        }
+    }
+}
+
+#[tracing::instrument(
+    skip_all,
+    fields(
+        newsletter_issue_id=tracing::field::Empty,
+        subscriber_email=tracing::field::Empty,
+    ),
+    err
+)]
+pub async fn try_execute_task(
+    db_pool: &PgPool,
+    email_client: &EmailClient,
+    unsubscribe_links: &UnsubscribeLinks,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let (mut txn, task) = match dequeue_task(db_pool).await? {
+        Some(t) => t,
+        None => return Ok(ExecutionOutcome::EmptyQueue),
+    };
+
+    Span::current()
+        .record("newsletter_issue_id", display(task.newsletter_issue_id))
+        .record("subscriber_email", display(&task.subscriber_email));
+
+    let subscriber_id = get_confirmed_subscriber_id(db_pool, &task.subscriber_email).await?;
+
+    match (
+        SubscriberEmail::parse(task.subscriber_email.clone()),
+        subscriber_id,
+    ) {
+        (Ok(email), Some(subscriber_id)) => {
+            let issue = get_issue(db_pool, task.newsletter_issue_id).await?;
+            let unsubscribe_link = unsubscribe_links.link(subscriber_id);
+            let html_body = format!(
+                "{}<p><a href=\"{}\">Unsubscribe</a></p>",
+                issue.html_content, unsubscribe_link
+            );
+            let text_body = format!(
+                "{}\n\nUnsubscribe: {}",
+                issue.text_content, unsubscribe_link
+            );
+
+            if let Err(e) = email_client
+                .send_list_email(
+                    email,
+                    &issue.title,
+                    &html_body,
+                    &text_body,
+                    &unsubscribe_link,
+                )
+                .await
+            {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "Failed to deliver issue to a confirmed subscriber.",
+                );
+                if task.n_retries + 1 < MAX_RETRIES {
+                    reschedule_task(&mut txn, &task).await?;
+                    txn.commit().await?;
+                    return Ok(ExecutionOutcome::TaskCompleted);
+                }
+                tracing::error!("Giving up on delivery after {} attempts.", MAX_RETRIES);
+            }
+        }
+        (Err(e), _) => {
+            tracing::warn!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Skipping a confirmed subscriber. Their stored contact details are invalid.",
+            );
+        }
+        (Ok(_), None) => {
+            tracing::info!("Skipping a subscriber who is no longer confirmed.");
+        }
+    }
+
+    delete_task(&mut txn, &task).await?;
+    txn.commit().await?;
+
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+#[tracing::instrument(skip_all)]
+async fn dequeue_task(
+    db_pool: &PgPool,
+) -> Result<Option<(Transaction<'static, Postgres>, Task)>, anyhow::Error> {
+    let mut txn = db_pool.begin().await?;
+
+    // SKIP LOCKED lets concurrent workers, possibly in other instances, each
+    // pick a different task instead of queueing up behind the same row.
+    let task = sqlx::query_as!(
+        Task,
+        r#"
+        SELECT newsletter_issue_id, subscriber_email, n_retries
+        FROM issue_delivery_queue
+        WHERE execute_after <= now()
+        FOR UPDATE
+        SKIP LOCKED
+        LIMIT 1
+        "#,
+    )
+    .fetch_optional(&mut txn)
+    .await?;
+
+    Ok(task.map(|t| (txn, t)))
+}
+
+#[tracing::instrument(skip_all)]
+async fn delete_task(
+    txn: &mut Transaction<'static, Postgres>,
+    task: &Task,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        DELETE FROM issue_delivery_queue
+        WHERE newsletter_issue_id = $1 AND subscriber_email = $2
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+    )
+    .execute(txn)
+    .await?;
+
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn reschedule_task(
+    txn: &mut Transaction<'static, Postgres>,
+    task: &Task,
+) -> Result<(), anyhow::Error> {
+    let delay_seconds = BASE_RETRY_DELAY_SECONDS * 2_i64.pow(task.n_retries as u32);
+
+    sqlx::query!(
+        r#"
+        UPDATE issue_delivery_queue
+        SET n_retries = n_retries + 1,
+            execute_after = now() + make_interval(secs => $3)
+        WHERE newsletter_issue_id = $1 AND subscriber_email = $2
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+        delay_seconds as f64,
+    )
+    .execute(txn)
+    .await?;
+
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_confirmed_subscriber_id(
+    db_pool: &PgPool,
+    subscriber_email: &str,
+) -> Result<Option<Uuid>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT id
+        FROM subscriptions
+        WHERE email = $1 AND status = 'confirmed'
+        "#,
+        subscriber_email,
+    )
+    .fetch_optional(db_pool)
+    .await?;
+
+    Ok(row.map(|r| r.id))
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_issue(
+    db_pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> Result<NewsletterIssue, anyhow::Error> {
+    let issue = sqlx::query_as!(
+        NewsletterIssue,
+        r#"
+        SELECT title, text_content, html_content
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id,
+    )
+    .fetch_one(db_pool)
+    .await?;
+
+    Ok(issue)
+}
@@ -1,6 +1,16 @@
+pub mod authentication;
+pub mod bot_protection;
 pub mod configuration;
 pub mod domain;
 pub mod email_client;
+pub mod health;
+pub mod idempotency;
+pub mod issue_delivery_worker;
+pub mod metrics;
+pub mod rate_limit;
 pub mod routes;
+pub mod session_state;
+pub mod session_store;
 pub mod startup;
 pub mod telemetry;
+pub mod unsubscribe;
@@ -1,16 +1,30 @@
 use zero2prod::{
     configuration::get_configuration,
     startup::Application,
-    telemetry::{get_subscriber, init_subscriber},
+    telemetry::{get_subscriber, init_subscriber, otlp_tracer, shutdown_tracing},
 };
 
 #[actix_web::main]
 async fn main() -> anyhow::Result<()> {
-    let subscriber = get_subscriber("zero2prod".to_string(), "info".to_string(), std::io::stdout);
+    let config = get_configuration()?;
+
+    let tracer = config
+        .telemetry
+        .otlp
+        .as_ref()
+        .map(otlp_tracer)
+        .transpose()?;
+    let subscriber = get_subscriber(
+        "zero2prod".to_string(),
+        "info".to_string(),
+        std::io::stdout,
+        &config.telemetry,
+        tracer,
+    );
     init_subscriber(subscriber);
 
-    let config = get_configuration().expect("Failed to load configuration");
     let app = Application::build(config).await?;
-    app.run_until_stopped().await?;
-    Ok(())
+    let outcome = app.run_until_stopped().await;
+    shutdown_tracing();
+    outcome
 }
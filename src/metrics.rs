@@ -0,0 +1,187 @@
+use std::time::Instant;
+
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    http::header::{AUTHORIZATION, CONTENT_TYPE, WWW_AUTHENTICATE},
+    web, HttpRequest, HttpResponse,
+};
+use actix_web_lab::middleware::Next;
+use once_cell::sync::Lazy;
+use prometheus::{
+    register_histogram_vec_with_registry, register_int_counter_vec_with_registry,
+    register_int_counter_with_registry, register_int_gauge_with_registry, Encoder, HistogramVec,
+    IntCounter, IntCounterVec, IntGauge, Registry, TextEncoder,
+};
+use secrecy::{ExposeSecret, Secret};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+
+/// Every metric the application exports, registered in its own registry so
+/// the `/metrics` output only contains what we define here.
+pub struct Metrics {
+    registry: Registry,
+    pub http_requests_total: IntCounterVec,
+    pub http_request_duration_seconds: HistogramVec,
+    pub db_pool_connections: IntGauge,
+    pub db_pool_idle_connections: IntGauge,
+    pub email_send_duration_seconds: HistogramVec,
+    pub email_api_responses_total: IntCounterVec,
+    pub signups_total: IntCounter,
+    pub signups_rejected_total: IntCounterVec,
+    pub confirmations_total: IntCounter,
+}
+
+pub static METRICS: Lazy<Metrics> = Lazy::new(|| {
+    let registry = Registry::new_custom(Some("zero2prod".into()), None)
+        .expect("Failed to create the metrics registry");
+
+    Metrics {
+        http_requests_total: register_int_counter_vec_with_registry!(
+            "http_requests_total",
+            "HTTP requests handled, by route pattern, method and status code.",
+            &["method", "route", "status"],
+            registry
+        )
+        .unwrap(),
+        http_request_duration_seconds: register_histogram_vec_with_registry!(
+            "http_request_duration_seconds",
+            "Time spent handling HTTP requests, by route pattern and method.",
+            &["method", "route"],
+            registry
+        )
+        .unwrap(),
+        db_pool_connections: register_int_gauge_with_registry!(
+            "db_pool_connections",
+            "Connections currently open in the database pool.",
+            registry
+        )
+        .unwrap(),
+        db_pool_idle_connections: register_int_gauge_with_registry!(
+            "db_pool_idle_connections",
+            "Open database connections that are not in use.",
+            registry
+        )
+        .unwrap(),
+        email_send_duration_seconds: register_histogram_vec_with_registry!(
+            "email_send_duration_seconds",
+            "Time taken to hand an email to the transport, retries included, by outcome.",
+            &["outcome"],
+            registry
+        )
+        .unwrap(),
+        email_api_responses_total: register_int_counter_vec_with_registry!(
+            "email_api_responses_total",
+            "Responses from the email API by status code; `error` when none arrived.",
+            &["status"],
+            registry
+        )
+        .unwrap(),
+        signups_total: register_int_counter_with_registry!(
+            "signups_total",
+            "Signups that were sent a confirmation email.",
+            registry
+        )
+        .unwrap(),
+        signups_rejected_total: register_int_counter_vec_with_registry!(
+            "signups_rejected_total",
+            "Signups taken for bots and silently dropped, by reason.",
+            &["reason"],
+            registry
+        )
+        .unwrap(),
+        confirmations_total: register_int_counter_with_registry!(
+            "confirmations_total",
+            "Subscriptions confirmed through an emailed link.",
+            registry
+        )
+        .unwrap(),
+        registry,
+    }
+});
+
+/// Counts and times every request by the route pattern it matched, so that
+/// path parameters don't blow up the number of series.
+pub async fn track_http_requests(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let started = Instant::now();
+    let method = req.method().to_string();
+    let route = req
+        .match_pattern()
+        .unwrap_or_else(|| "unmatched".to_string());
+
+    let res = next.call(req).await;
+
+    let status = match &res {
+        Ok(res) => res.status(),
+        Err(e) => e.as_response_error().status_code(),
+    };
+    METRICS
+        .http_requests_total
+        .with_label_values(&[&method, &route, status.as_str()])
+        .inc();
+    METRICS
+        .http_request_duration_seconds
+        .with_label_values(&[&method, &route])
+        .observe(started.elapsed().as_secs_f64());
+
+    res
+}
+
+/// The token scrapers must present to read `/metrics`.
+pub struct MetricsBearerToken(pub Secret<String>);
+
+impl MetricsBearerToken {
+    /// Compares digests, so that how long the comparison takes says nothing
+    /// about how much of the token was right.
+    fn is_presented_by(&self, request: &HttpRequest) -> bool {
+        let presented = request
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        match presented {
+            Some(presented) => {
+                Sha256::digest(presented.as_bytes())
+                    == Sha256::digest(self.0.expose_secret().as_bytes())
+            }
+            None => false,
+        }
+    }
+}
+
+/// Only served to requests bearing the configured token, and not at all when
+/// there is none.
+pub async fn metrics(
+    db_pool: web::Data<PgPool>,
+    token: Option<web::Data<MetricsBearerToken>>,
+    request: HttpRequest,
+) -> HttpResponse {
+    match token {
+        None => return HttpResponse::NotFound().finish(),
+        Some(token) if !token.is_presented_by(&request) => {
+            return HttpResponse::Unauthorized()
+                .insert_header((WWW_AUTHENTICATE, r#"Bearer realm="metrics""#))
+                .finish()
+        }
+        Some(_) => {}
+    }
+
+    METRICS.db_pool_connections.set(db_pool.size().into());
+    METRICS
+        .db_pool_idle_connections
+        .set(db_pool.num_idle() as i64);
+
+    let encoder = TextEncoder::new();
+    let mut buffer = Vec::new();
+    if let Err(e) = encoder.encode(&METRICS.registry.gather(), &mut buffer) {
+        tracing::error!("Failed to encode metrics: {:?}", e);
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    HttpResponse::Ok()
+        .insert_header((CONTENT_TYPE, encoder.format_type()))
+        .body(buffer)
+}
@@ -0,0 +1,51 @@
+use std::{collections::HashMap, sync::Mutex, time::Instant};
+
+use super::{Decision, Quota, RateLimitStore};
+
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+    full_at: Instant,
+}
+
+/// Buckets kept in this process, for single-instance deployments.
+#[derive(Default)]
+pub struct InMemoryStore {
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl InMemoryStore {
+    /// Above this many buckets, the ones that have refilled are dropped.
+    const PRUNE_ABOVE: usize = 10_000;
+}
+
+#[async_trait::async_trait]
+impl RateLimitStore for InMemoryStore {
+    async fn acquire(&self, key: &str, quota: Quota) -> Result<Decision, anyhow::Error> {
+        let now = Instant::now();
+        let mut buckets = self
+            .buckets
+            .lock()
+            .map_err(|_| anyhow::anyhow!("The rate limit buckets are poisoned"))?;
+
+        if buckets.len() > Self::PRUNE_ABOVE {
+            buckets.retain(|_, bucket| bucket.full_at > now);
+        }
+
+        let tokens = match buckets.get(key) {
+            Some(bucket) => quota.refill(bucket.tokens, now - bucket.updated_at),
+            None => quota.capacity as f64,
+        };
+        let (tokens, decision) = quota.take(tokens);
+        buckets.insert(
+            key.to_string(),
+            Bucket {
+                tokens,
+                updated_at: now,
+                full_at: now + quota.time_to_full(tokens),
+            },
+        );
+
+        Ok(decision)
+    }
+}
@@ -0,0 +1,29 @@
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    error::InternalError,
+    web,
+};
+use actix_web_lab::middleware::Next;
+
+use super::{too_many_requests, Decision, RateLimiter};
+
+/// Rejects requests from clients that have used up their per-IP quota. Does
+/// nothing when rate limiting is disabled.
+pub async fn limit_by_client_ip(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let limiter = req.app_data::<web::Data<RateLimiter>>().cloned();
+    let peer = req.peer_addr().map(|addr| addr.ip());
+
+    if let (Some(limiter), Some(peer)) = (limiter, peer) {
+        let client_ip = limiter.client_ip(peer, req.headers());
+        if let Decision::Limited { retry_after } = limiter.check_ip(client_ip).await {
+            let e = anyhow::anyhow!("The client has exceeded its rate limit");
+            return Err(InternalError::from_response(e, too_many_requests(retry_after)).into());
+        }
+    }
+
+    next.call(req).await
+}
@@ -0,0 +1,294 @@
+mod memory;
+mod middleware;
+mod postgres;
+
+use std::{net::IpAddr, sync::Arc, time::Duration};
+
+use actix_web::{
+    http::{
+        header::{HeaderMap, RETRY_AFTER},
+        StatusCode,
+    },
+    HttpResponse,
+};
+use ipnet::{IpNet, Ipv6Net};
+use sha2::{Digest, Sha256};
+
+pub use memory::*;
+pub use middleware::*;
+pub use postgres::*;
+
+use crate::{domain::SubscriberEmail, routes::ProblemDetails};
+
+/// How many requests a bucket allows in a burst, and how long it takes to
+/// earn another one.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quota {
+    pub capacity: u32,
+    pub refill_interval: Duration,
+}
+
+impl Quota {
+    /// Tops `tokens` up for the time since the bucket was last used.
+    fn refill(&self, tokens: f64, elapsed: Duration) -> f64 {
+        let earned = elapsed.as_secs_f64() / self.refill_interval.as_secs_f64();
+        (tokens + earned).min(self.capacity as f64)
+    }
+
+    /// Takes a token from a bucket holding `tokens`, returning what is left and
+    /// whether the request may go ahead.
+    fn take(&self, tokens: f64) -> (f64, Decision) {
+        if tokens >= 1.0 {
+            (tokens - 1.0, Decision::Allowed)
+        } else {
+            let retry_after = self.refill_interval.mul_f64(1.0 - tokens);
+            (tokens, Decision::Limited { retry_after })
+        }
+    }
+
+    /// How long a bucket holding `tokens` takes to fill up again, after which
+    /// it can be forgotten.
+    fn time_to_full(&self, tokens: f64) -> Duration {
+        self.refill_interval
+            .mul_f64((self.capacity as f64 - tokens).max(0.0))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Decision {
+    Allowed,
+    Limited { retry_after: Duration },
+}
+
+/// Keeps the token buckets, either in this process or somewhere shared by
+/// every instance.
+#[async_trait::async_trait]
+pub trait RateLimitStore: Send + Sync {
+    /// Takes a token from the bucket under `key`, starting from a full bucket
+    /// if there is none yet.
+    async fn acquire(&self, key: &str, quota: Quota) -> Result<Decision, anyhow::Error>;
+}
+
+/// Limits signups and confirmations per client IP and per target email.
+#[derive(Clone)]
+pub struct RateLimiter {
+    store: Arc<dyn RateLimitStore>,
+    per_ip: Quota,
+    per_email: Quota,
+    trusted_proxies: Arc<Vec<IpNet>>,
+}
+
+impl RateLimiter {
+    pub fn new(
+        store: impl RateLimitStore + 'static,
+        per_ip: Quota,
+        per_email: Quota,
+        trusted_proxies: Vec<IpNet>,
+    ) -> Self {
+        Self {
+            store: Arc::new(store),
+            per_ip,
+            per_email,
+            trusted_proxies: Arc::new(trusted_proxies),
+        }
+    }
+
+    /// IPv6 clients are limited by their /64, the smallest block usually
+    /// assigned to a single subscriber, so rotating through the addresses of
+    /// one network doesn't get around the limit.
+    pub async fn check_ip(&self, ip: IpAddr) -> Decision {
+        self.check(&ip_key(ip), self.per_ip).await
+    }
+
+    /// Emails are keyed by their hash, so the store never holds addresses.
+    pub async fn check_email(&self, email: &SubscriberEmail) -> Decision {
+        let hash = Sha256::digest(email.as_ref().to_lowercase().as_bytes());
+        self.check(&format!("email:{:x}", hash), self.per_email)
+            .await
+    }
+
+    /// A store that is unavailable lets requests through: failing to limit is
+    /// better than failing every signup.
+    async fn check(&self, key: &str, quota: Quota) -> Decision {
+        match self.store.acquire(key, quota).await {
+            Ok(decision) => decision,
+            Err(e) => {
+                tracing::warn!(
+                    error.message = %e,
+                    "Failed to check the rate limit, allowing the request"
+                );
+                Decision::Allowed
+            }
+        }
+    }
+
+    /// The address of the client behind `peer`. `X-Forwarded-For` is only
+    /// trusted as far back as the chain of trusted proxies goes, so clients
+    /// can't pick their own address.
+    pub fn client_ip(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        let is_trusted = |ip: &IpAddr| self.trusted_proxies.iter().any(|net| net.contains(ip));
+        if !is_trusted(&peer) {
+            return peer;
+        }
+
+        let forwarded: Vec<&str> = headers
+            .get_all("X-Forwarded-For")
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .collect();
+
+        let mut client = peer;
+        for hop in forwarded.iter().rev() {
+            match hop.parse::<IpAddr>() {
+                Ok(ip) => {
+                    client = ip;
+                    if !is_trusted(&ip) {
+                        break;
+                    }
+                }
+                Err(_) => break,
+            }
+        }
+        client
+    }
+}
+
+fn ip_key(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => format!("ip:{}", ip),
+        IpAddr::V6(ip) => format!(
+            "ip:{}",
+            Ipv6Net::new(ip, 64)
+                .expect("64 is a valid IPv6 prefix length")
+                .trunc()
+        ),
+    }
+}
+
+/// A 429 telling the client when to come back, in whole seconds.
+pub fn too_many_requests(retry_after: Duration) -> HttpResponse {
+    let seconds = retry_after.as_secs_f64().ceil().max(1.0) as u64;
+    let mut response = ProblemDetails::new(
+        StatusCode::TOO_MANY_REQUESTS,
+        format!("Too many requests, retry in {} seconds.", seconds),
+    )
+    .into_response();
+    response.headers_mut().insert(RETRY_AFTER, seconds.into());
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::IpAddr, time::Duration};
+
+    use actix_web::http::header::{HeaderMap, HeaderName, HeaderValue};
+
+    use super::{Decision, InMemoryStore, Quota, RateLimiter};
+
+    const QUOTA: Quota = Quota {
+        capacity: 2,
+        refill_interval: Duration::from_secs(10),
+    };
+
+    fn limiter(trusted_proxies: &[&str]) -> RateLimiter {
+        RateLimiter::new(
+            InMemoryStore::default(),
+            QUOTA,
+            QUOTA,
+            trusted_proxies.iter().map(|p| p.parse().unwrap()).collect(),
+        )
+    }
+
+    fn forwarded_for(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            HeaderName::from_static("x-forwarded-for"),
+            HeaderValue::from_str(value).unwrap(),
+        );
+        headers
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn buckets_refill_up_to_their_capacity() {
+        assert_eq!(QUOTA.refill(0.0, Duration::from_secs(5)), 0.5);
+        assert_eq!(QUOTA.refill(1.0, Duration::from_secs(3600)), 2.0);
+    }
+
+    #[test]
+    fn empty_buckets_say_when_the_next_token_arrives() {
+        assert_eq!(QUOTA.take(1.5), (0.5, Decision::Allowed));
+        assert_eq!(
+            QUOTA.take(0.5),
+            (
+                0.5,
+                Decision::Limited {
+                    retry_after: Duration::from_secs(5)
+                }
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn requests_over_the_quota_are_limited() {
+        let limiter = limiter(&[]);
+
+        assert_eq!(limiter.check_ip(ip("10.0.0.1")).await, Decision::Allowed);
+        assert_eq!(limiter.check_ip(ip("10.0.0.1")).await, Decision::Allowed);
+        assert!(matches!(
+            limiter.check_ip(ip("10.0.0.1")).await,
+            Decision::Limited { .. }
+        ));
+        assert_eq!(limiter.check_ip(ip("10.0.0.2")).await, Decision::Allowed);
+    }
+
+    #[tokio::test]
+    async fn ipv6_clients_share_the_quota_of_their_network() {
+        let limiter = limiter(&[]);
+
+        assert_eq!(limiter.check_ip(ip("2001:db8::1")).await, Decision::Allowed);
+        assert_eq!(limiter.check_ip(ip("2001:db8::2")).await, Decision::Allowed);
+        assert!(matches!(
+            limiter.check_ip(ip("2001:db8::ffff:3")).await,
+            Decision::Limited { .. }
+        ));
+        assert_eq!(
+            limiter.check_ip(ip("2001:db8:0:1::1")).await,
+            Decision::Allowed
+        );
+    }
+
+    #[test]
+    fn forwarded_for_is_ignored_from_untrusted_peers() {
+        let limiter = limiter(&["10.0.0.0/8"]);
+
+        let client = limiter.client_ip(ip("192.0.2.1"), &forwarded_for("203.0.113.7"));
+
+        assert_eq!(client, ip("192.0.2.1"));
+    }
+
+    #[test]
+    fn forwarded_for_is_followed_through_trusted_proxies_only() {
+        let limiter = limiter(&["10.0.0.0/8"]);
+
+        let client = limiter.client_ip(
+            ip("10.0.0.1"),
+            &forwarded_for("198.51.100.1, 203.0.113.7, 10.0.0.2"),
+        );
+
+        assert_eq!(client, ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn a_chain_of_trusted_proxies_falls_back_to_the_last_hop() {
+        let limiter = limiter(&["10.0.0.0/8"]);
+
+        let client = limiter.client_ip(ip("10.0.0.1"), &forwarded_for("garbage, 10.0.0.2"));
+
+        assert_eq!(client, ip("10.0.0.2"));
+    }
+}
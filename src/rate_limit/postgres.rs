@@ -0,0 +1,98 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use anyhow::Context;
+use sqlx::PgPool;
+
+use super::{Decision, Quota, RateLimitStore};
+
+/// Buckets kept in the `rate_limit_buckets` table, so that every instance
+/// behind a load balancer enforces the same limits.
+pub struct PostgresStore {
+    db_pool: PgPool,
+    acquisitions: AtomicU64,
+}
+
+impl PostgresStore {
+    /// Buckets that have refilled are deleted once every this many requests.
+    const PRUNE_EVERY: u64 = 1_000;
+
+    pub fn new(db_pool: PgPool) -> Self {
+        Self {
+            db_pool,
+            acquisitions: AtomicU64::new(0),
+        }
+    }
+
+    #[tracing::instrument(name = "Pruning full rate limit buckets", skip(self))]
+    async fn prune(&self) -> Result<(), sqlx::Error> {
+        sqlx::query!("DELETE FROM rate_limit_buckets WHERE full_at <= now()")
+            .execute(&self.db_pool)
+            .await?;
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl RateLimitStore for PostgresStore {
+    #[tracing::instrument(name = "Acquiring a rate limit token", skip_all)]
+    async fn acquire(&self, key: &str, quota: Quota) -> Result<Decision, anyhow::Error> {
+        if self
+            .acquisitions
+            .fetch_add(1, Ordering::Relaxed)
+            .is_multiple_of(Self::PRUNE_EVERY)
+        {
+            self.prune()
+                .await
+                .context("Failed to prune rate limit buckets")?;
+        }
+
+        let mut txn = self.db_pool.begin().await?;
+
+        // The database clock is used throughout, so instances with drifting
+        // clocks still agree on how full a bucket is.
+        sqlx::query!(
+            r#"
+            INSERT INTO rate_limit_buckets (key, tokens, updated_at, full_at)
+            VALUES ($1, $2, now(), now())
+            ON CONFLICT DO NOTHING
+            "#,
+            key,
+            quota.capacity as f64,
+        )
+        .execute(&mut txn)
+        .await?;
+
+        let bucket = sqlx::query!(
+            r#"
+            SELECT
+                tokens,
+                EXTRACT(EPOCH FROM now() - updated_at)::float8 AS "elapsed_seconds!"
+            FROM rate_limit_buckets
+            WHERE key = $1
+            FOR UPDATE
+            "#,
+            key,
+        )
+        .fetch_one(&mut txn)
+        .await?;
+
+        let elapsed = std::time::Duration::from_secs_f64(bucket.elapsed_seconds.max(0.0));
+        let (tokens, decision) = quota.take(quota.refill(bucket.tokens, elapsed));
+
+        sqlx::query!(
+            r#"
+            UPDATE rate_limit_buckets
+            SET tokens = $2, updated_at = now(), full_at = now() + make_interval(secs => $3)
+            WHERE key = $1
+            "#,
+            key,
+            tokens,
+            quota.time_to_full(tokens).as_secs_f64(),
+        )
+        .execute(&mut txn)
+        .await?;
+
+        txn.commit().await?;
+        Ok(decision)
+    }
+}
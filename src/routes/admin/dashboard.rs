@@ -0,0 +1,55 @@
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::authentication::UserId;
+
+pub async fn admin_dashboard(
+    user_id: web::ReqData<UserId>,
+    db_pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let username = match get_username(**user_id, &db_pool).await {
+        Ok(u) => u,
+        Err(e) => {
+            tracing::error!("Failed to look up the logged-in user: {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+  <head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8" />
+    <title>Admin dashboard</title>
+  </head>
+  <body>
+    <p>Welcome {}!</p>
+    <form name="logoutForm" action="/logout" method="post">
+      <input type="submit" value="Logout" />
+    </form>
+  </body>
+</html>"#,
+            htmlescape::encode_minimal(&username)
+        ))
+}
+
+#[tracing::instrument(name = "Get username", skip(db_pool))]
+pub async fn get_username(user_id: Uuid, db_pool: &PgPool) -> Result<String, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT username
+        FROM users
+        WHERE user_id = $1
+        "#,
+        user_id,
+    )
+    .fetch_one(db_pool)
+    .await
+    .context("Failed to perform a query to retrieve a username")?;
+
+    Ok(row.username)
+}
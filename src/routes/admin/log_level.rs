@@ -0,0 +1,81 @@
+use std::fmt;
+
+use actix_web::{http::StatusCode, web, HttpResponse, ResponseError};
+use anyhow::Context;
+
+use crate::{
+    authentication::UserId,
+    routes::{error_chain_fmt, FieldError, ProblemDetails},
+    telemetry::{current_log_filter, parse_log_filter, set_log_filter, LogFilterError},
+};
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct LogLevel {
+    /// Filter directives in `RUST_LOG` syntax, e.g.
+    /// `info,zero2prod::email_client=debug`.
+    filter: String,
+}
+
+#[derive(thiserror::Error)]
+pub enum LogLevelError {
+    #[error(transparent)]
+    InvalidFilter(LogFilterError),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl fmt::Debug for LogLevelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for LogLevelError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::InvalidFilter(_) => StatusCode::BAD_REQUEST,
+            Self::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            Self::InvalidFilter(e) => {
+                ProblemDetails::new(self.status_code(), "The log filter is invalid.")
+                    .with_errors(&[FieldError::new("filter", e)])
+            }
+            Self::UnexpectedError(_) => ProblemDetails::new(
+                self.status_code(),
+                "The log filter could not be read or changed.",
+            ),
+        }
+        .into_response()
+    }
+}
+
+pub async fn get_log_level() -> Result<HttpResponse, LogLevelError> {
+    let filter = current_log_filter().context("Failed to read the log filter")?;
+    Ok(HttpResponse::Ok().json(LogLevel { filter }))
+}
+
+#[tracing::instrument(name = "Changing the log level", skip(body), fields(user_id=%*user_id))]
+pub async fn put_log_level(
+    body: web::Json<LogLevel>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, LogLevelError> {
+    let filter = parse_log_filter(&body.filter).map_err(LogLevelError::InvalidFilter)?;
+    let previous = current_log_filter().context("Failed to read the log filter")?;
+
+    // Logged before the change, which could otherwise filter it out.
+    tracing::info!(
+        target: "audit",
+        user_id = %*user_id,
+        previous_filter = %previous,
+        filter = %body.filter,
+        "Changing the log filter",
+    );
+    set_log_filter(filter).context("Failed to change the log filter")?;
+    Ok(HttpResponse::Ok().json(LogLevel {
+        filter: body.into_inner().filter,
+    }))
+}
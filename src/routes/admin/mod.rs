@@ -0,0 +1,5 @@
+mod dashboard;
+mod log_level;
+
+pub use dashboard::*;
+pub use log_level::*;
@@ -1,5 +1,27 @@
-use actix_web::HttpResponse;
+use actix_web::{web, HttpResponse};
+use sqlx::PgPool;
+
+use crate::health::{ReadinessProbe, Status};
 
 pub async fn health_check() -> HttpResponse {
     HttpResponse::Ok().finish()
 }
+
+pub async fn liveness() -> HttpResponse {
+    HttpResponse::Ok().json(serde_json::json!({ "status": Status::Up }))
+}
+
+#[tracing::instrument(name = "Checking readiness", skip_all)]
+pub async fn readiness(
+    db_pool: web::Data<PgPool>,
+    probe: web::Data<ReadinessProbe>,
+) -> HttpResponse {
+    let readiness = probe.check(&db_pool).await;
+
+    if readiness.status == Status::Up {
+        HttpResponse::Ok().json(readiness)
+    } else {
+        tracing::warn!("Instance is not ready");
+        HttpResponse::ServiceUnavailable().json(readiness)
+    }
+}
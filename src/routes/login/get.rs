@@ -0,0 +1,40 @@
+use std::fmt::Write;
+
+use actix_web::{http::header::ContentType, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+
+pub async fn login_form(flash_messages: IncomingFlashMessages) -> HttpResponse {
+    let mut messages_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(
+            messages_html,
+            "<p><i>{}</i></p>",
+            htmlescape::encode_minimal(m.content())
+        )
+        .unwrap();
+    }
+
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+  <head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8" />
+    <title>Login</title>
+  </head>
+  <body>
+    {messages_html}
+    <form action="/login" method="post">
+      <label>Username
+        <input type="text" placeholder="Enter Username" name="username" />
+      </label>
+      <label>Password
+        <input type="password" placeholder="Enter Password" name="password" />
+      </label>
+      <button type="submit">Login</button>
+    </form>
+  </body>
+</html>"#
+        ))
+}
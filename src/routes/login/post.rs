@@ -0,0 +1,68 @@
+use actix_web::{http::header::LOCATION, web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use secrecy::Secret;
+use sqlx::PgPool;
+
+use crate::{
+    authentication::{validate_credentials, AuthError, Credentials},
+    session_state::TypedSession,
+};
+
+#[derive(serde::Deserialize)]
+pub struct LoginData {
+    username: String,
+    password: Secret<String>,
+}
+
+#[tracing::instrument(
+    name = "Logging in",
+    skip(form, db_pool, session),
+    fields(
+        username=tracing::field::Empty,
+        user_id=tracing::field::Empty,
+    ),
+)]
+pub async fn login(
+    form: web::Form<LoginData>,
+    db_pool: web::Data<PgPool>,
+    session: TypedSession,
+) -> HttpResponse {
+    let credentials = Credentials {
+        username: form.0.username,
+        password: form.0.password,
+    };
+    tracing::Span::current().record("username", tracing::field::display(&credentials.username));
+
+    match validate_credentials(credentials, &db_pool).await {
+        Ok(user_id) => {
+            tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+
+            // A fresh session key on every login prevents session fixation.
+            session.renew();
+            if let Err(e) = session.insert_user_id(user_id) {
+                tracing::error!("Failed to store the user in the session: {:?}", e);
+                return redirect_to_login("Something went wrong. Please try again.");
+            }
+
+            HttpResponse::SeeOther()
+                .insert_header((LOCATION, "/admin/dashboard"))
+                .finish()
+        }
+        Err(AuthError::InvalidCredentials(e)) => {
+            tracing::warn!("Rejected credentials: {:?}", e);
+            redirect_to_login("Authentication failed.")
+        }
+        Err(AuthError::UnexpectedError(e)) => {
+            tracing::error!("Failed to validate credentials: {:?}", e);
+            redirect_to_login("Something went wrong. Please try again.")
+        }
+    }
+}
+
+fn redirect_to_login(message: &str) -> HttpResponse {
+    FlashMessage::error(message.to_string()).send();
+
+    HttpResponse::SeeOther()
+        .insert_header((LOCATION, "/login"))
+        .finish()
+}
@@ -0,0 +1,13 @@
+use actix_web::{http::header::LOCATION, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+
+use crate::session_state::TypedSession;
+
+pub async fn log_out(session: TypedSession) -> HttpResponse {
+    session.log_out();
+    FlashMessage::info("You have successfully logged out.").send();
+
+    HttpResponse::SeeOther()
+        .insert_header((LOCATION, "/login"))
+        .finish()
+}
@@ -1,7 +1,25 @@
+mod admin;
 mod health_check;
+mod home;
+mod login;
+mod logout;
+mod newsletters;
+mod openapi;
+mod problem;
 mod subscriptions;
+mod subscriptions_challenge;
 mod subscriptions_confirm;
+mod subscriptions_unsubscribe;
 
+pub use admin::*;
 pub use health_check::*;
+pub use home::*;
+pub use login::*;
+pub use logout::*;
+pub use newsletters::*;
+pub use openapi::*;
+pub use problem::*;
 pub use subscriptions::*;
+pub use subscriptions_challenge::*;
 pub use subscriptions_confirm::*;
+pub use subscriptions_unsubscribe::*;
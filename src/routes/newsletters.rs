@@ -0,0 +1,163 @@
+use actix_web::{http::header, web, HttpRequest, HttpResponse};
+use chrono::Utc;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    authentication::{basic_authentication, validate_credentials, AuthError},
+    idempotency::{
+        begin_request, complete_request, IdempotencyKey, NextAction, RequestFingerprint,
+    },
+};
+
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct BodyData {
+    title: String,
+    content: Content,
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct Content {
+    html: String,
+    text: String,
+}
+
+#[tracing::instrument(
+    name = "Publishing a newsletter issue",
+    skip(body, db_pool, request),
+    fields(
+        newsletter_title=%body.title,
+        username=tracing::field::Empty,
+        user_id=tracing::field::Empty,
+    ),
+)]
+pub async fn publish_newsletter(
+    body: web::Json<BodyData>,
+    db_pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> HttpResponse {
+    let credentials = match basic_authentication(request.headers()) {
+        Ok(c) => c,
+        Err(e) => {
+            tracing::warn!("Missing or malformed credentials: {:?}", e);
+            return unauthorized();
+        }
+    };
+    tracing::Span::current().record("username", tracing::field::display(&credentials.username));
+
+    let user_id = match validate_credentials(credentials, &db_pool).await {
+        Ok(user_id) => {
+            tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+            user_id
+        }
+        Err(AuthError::InvalidCredentials(e)) => {
+            tracing::warn!("Rejected credentials: {:?}", e);
+            return unauthorized();
+        }
+        Err(AuthError::UnexpectedError(e)) => {
+            tracing::error!("Failed to validate credentials: {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let idempotency_key = match IdempotencyKey::from_headers(request.headers()) {
+        Ok(k) => k,
+        Err(_) => return HttpResponse::BadRequest().finish(),
+    };
+
+    let fingerprint = match RequestFingerprint::new(&request, &body.0) {
+        Ok(f) => f,
+        Err(e) => {
+            tracing::error!("Failed to fingerprint the request: {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let mut txn =
+        match begin_request(&db_pool, idempotency_key.as_ref(), user_id, &fingerprint).await {
+            Ok(NextAction::StartProcessing(t)) => t,
+            Ok(NextAction::ReturnSavedResponse(saved_response)) => return saved_response,
+            Err(e) => {
+                tracing::error!("Failed to start processing the request: {:?}", e);
+                return HttpResponse::InternalServerError().finish();
+            }
+        };
+
+    let issue_id = match insert_newsletter_issue(&mut txn, &body).await {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+
+    if enqueue_delivery_tasks(&mut txn, issue_id).await.is_err() {
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    let response = HttpResponse::Ok().finish();
+    match complete_request(txn, idempotency_key.as_ref(), user_id, response).await {
+        Ok(response) => response,
+        Err(e) => {
+            tracing::error!("Failed to complete the request: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+fn unauthorized() -> HttpResponse {
+    HttpResponse::Unauthorized()
+        .insert_header((header::WWW_AUTHENTICATE, r#"Basic realm="publish""#))
+        .finish()
+}
+
+#[tracing::instrument(name = "Saving a newsletter issue", skip(txn, body))]
+async fn insert_newsletter_issue(
+    txn: &mut Transaction<'_, Postgres>,
+    body: &BodyData,
+) -> Result<Uuid, sqlx::Error> {
+    let newsletter_issue_id = Uuid::new_v4();
+
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issues (
+            newsletter_issue_id, title, text_content, html_content, published_at
+        )
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        newsletter_issue_id,
+        body.title,
+        body.content.text,
+        body.content.html,
+        Utc::now(),
+    )
+    .execute(txn)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+
+    Ok(newsletter_issue_id)
+}
+
+#[tracing::instrument(name = "Enqueueing delivery tasks", skip(txn))]
+async fn enqueue_delivery_tasks(
+    txn: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_queue (newsletter_issue_id, subscriber_email)
+        SELECT $1, email
+        FROM subscriptions
+        WHERE status = 'confirmed'
+        "#,
+        newsletter_issue_id,
+    )
+    .execute(txn)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+
+    Ok(())
+}
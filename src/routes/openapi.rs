@@ -0,0 +1,60 @@
+use actix_web::{web, HttpResponse};
+use utoipa::{
+    openapi::{path::Operation, OpenApi as OpenApiDocument, PathItemType},
+    Modify, OpenApi,
+};
+
+use crate::routes::{
+    __path_api_unsubscribe, __path_confirm_subscription, __path_subscribe,
+    __path_subscription_challenge, FieldError, FormChallenge, FormData, ProblemDetails,
+    SubscriptionAccepted,
+};
+
+/// The OpenAPI document of the `/api/v1` scope, generated from the handlers
+/// and the types they read and write.
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "zero2prod", description = "Newsletter subscription API."),
+    paths(subscribe, subscription_challenge, confirm_subscription, api_unsubscribe),
+    components(schemas(
+        FormData,
+        SubscriptionAccepted,
+        FormChallenge,
+        ProblemDetails,
+        FieldError
+    )),
+    modifiers(&FormBodies),
+    tags(
+        (name = "subscriptions", description = "Signing up for the newsletter."),
+        (name = "subscribers", description = "Managing an existing subscription."),
+    ),
+)]
+pub struct ApiDoc;
+
+/// `utoipa::path` only takes one request content type, so the form encoding
+/// accepted by `subscribe` is added here from the JSON one.
+struct FormBodies;
+
+impl Modify for FormBodies {
+    fn modify(&self, openapi: &mut OpenApiDocument) {
+        let subscribe = openapi
+            .paths
+            .paths
+            .get_mut("/api/v1/subscriptions")
+            .and_then(|item| item.operations.get_mut(&PathItemType::Post));
+        if let Some(Operation {
+            request_body: Some(body),
+            ..
+        }) = subscribe
+        {
+            if let Some(json) = body.content.get("application/json").cloned() {
+                body.content
+                    .insert("application/x-www-form-urlencoded".to_string(), json);
+            }
+        }
+    }
+}
+
+pub async fn openapi_json(doc: web::Data<OpenApiDocument>) -> HttpResponse {
+    HttpResponse::Ok().json(doc.get_ref())
+}
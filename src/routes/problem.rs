@@ -0,0 +1,149 @@
+use std::fmt;
+
+use actix_web::{http::StatusCode, HttpResponse};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+pub const PROBLEM_JSON_CONTENT_TYPE: &str = "application/problem+json";
+
+/// Writes an error followed by every error in its `source()` chain, so the
+/// cause of a failure is logged alongside what the handler was doing.
+pub fn error_chain_fmt(e: &impl std::error::Error, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    writeln!(f, "{}", e)?;
+    let mut current = e.source();
+    while let Some(cause) = current {
+        writeln!(f, "Caused by:\n\t{}", cause)?;
+        current = cause.source();
+    }
+    Ok(())
+}
+
+/// A validation problem with a single field of the request.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct FieldError {
+    pub field: &'static str,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: &'static str, message: impl ToString) -> Self {
+        Self {
+            field,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for FieldError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
+/// Every field that failed validation, rather than just the first one.
+#[derive(Debug, Clone)]
+pub struct FieldErrors(pub Vec<FieldError>);
+
+impl fmt::Display for FieldErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, error) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{}", error)?;
+        }
+        Ok(())
+    }
+}
+
+/// An RFC 7807 problem details body.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ProblemDetails {
+    #[serde(rename = "type")]
+    #[schema(example = "about:blank")]
+    problem_type: &'static str,
+    #[schema(example = "Bad Request")]
+    title: &'static str,
+    #[schema(example = 400)]
+    status: u16,
+    detail: String,
+    /// The fields that failed validation, if any.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    errors: Vec<FieldError>,
+}
+
+impl ProblemDetails {
+    /// A problem with no further semantics than its status code, as described
+    /// by the `about:blank` type.
+    pub fn new(status: StatusCode, detail: impl ToString) -> Self {
+        Self {
+            problem_type: "about:blank",
+            title: status.canonical_reason().unwrap_or("Unknown Error"),
+            status: status.as_u16(),
+            detail: detail.to_string(),
+            errors: Vec::new(),
+        }
+    }
+
+    pub fn with_errors(mut self, errors: &[FieldError]) -> Self {
+        self.errors = errors.to_vec();
+        self
+    }
+
+    pub fn into_response(self) -> HttpResponse {
+        let status = StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        HttpResponse::build(status)
+            .content_type(PROBLEM_JSON_CONTENT_TYPE)
+            .json(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt;
+
+    use actix_web::{body::to_bytes, http::StatusCode};
+
+    use super::{error_chain_fmt, FieldError, ProblemDetails};
+
+    #[derive(thiserror::Error)]
+    #[error("outer")]
+    struct Outer(#[source] std::io::Error);
+
+    impl fmt::Debug for Outer {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            error_chain_fmt(self, f)
+        }
+    }
+
+    #[test]
+    fn error_chain_includes_every_cause() {
+        let e = Outer(std::io::Error::other("inner"));
+        assert_eq!(format!("{:?}", e), "outer\nCaused by:\n\tinner\n");
+    }
+
+    #[actix_web::test]
+    async fn problem_details_are_serialized_as_problem_json() {
+        let response = ProblemDetails::new(StatusCode::BAD_REQUEST, "bad input")
+            .with_errors(&[FieldError::new("email", "is required")])
+            .into_response();
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            response.headers().get("Content-Type").unwrap(),
+            "application/problem+json"
+        );
+        let body: serde_json::Value =
+            serde_json::from_slice(&to_bytes(response.into_body()).await.unwrap()).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "type": "about:blank",
+                "title": "Bad Request",
+                "status": 400,
+                "detail": "bad input",
+                "errors": [{"field": "email", "message": "is required"}],
+            })
+        );
+    }
+}
@@ -1,108 +1,480 @@
-use actix_web::{web, HttpResponse};
+use std::{fmt, future::Future, pin::Pin, time::Duration};
+
+use actix_web::{
+    dev::Payload,
+    http::{
+        header::{Accept, Header},
+        StatusCode,
+    },
+    web, FromRequest, HttpMessage, HttpRequest, HttpResponse, ResponseError,
+};
+use anyhow::Context;
 use chrono::Utc;
-use sqlx::PgPool;
+use sqlx::{PgPool, Postgres, Transaction};
+use utoipa::ToSchema;
 use uuid::Uuid;
 
 use crate::{
-    domain::{NewSubscriber, SubscriberEmail, SubscriberName},
+    bot_protection::{BotCheckError, BotProtection, Submission},
+    domain::{EmailPolicy, NewSubscriber, SubscriberEmail, SubscriberName, SubscriptionToken},
     email_client::EmailClient,
+    idempotency::{
+        anonymous_user_id, begin_request, complete_request, IdempotencyKey, IdempotencyKeyError,
+        NextAction, RequestFingerprint, ANONYMOUS_USER_ID,
+    },
+    metrics::METRICS,
+    rate_limit::{too_many_requests, Decision, RateLimiter},
+    routes::{error_chain_fmt, FieldError, FieldErrors, ProblemDetails},
+    startup::{ApplicationBaseUrl, ConfirmationTokenTtl},
 };
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, serde::Serialize, ToSchema)]
 pub struct FormData {
+    // Missing fields are reported as validation errors along with the rest,
+    // instead of failing the whole body.
+    #[serde(default)]
+    #[schema(example = "Ursula Le Guin")]
     name: String,
+    #[serde(default)]
+    #[schema(example = "ursula_le_guin@gmail.com")]
     email: String,
+    /// Hidden from people by the signup form and expected to stay empty.
+    #[serde(default)]
+    website: Option<String>,
+    /// The token from `/subscriptions/challenge`, fetched when the form loads.
+    #[serde(default)]
+    form_token: Option<String>,
+    /// Solves the proof of work for `form_token` and `email`, when one is
+    /// required.
+    #[serde(default)]
+    proof_of_work: Option<String>,
+}
+
+impl FormData {
+    fn submission(&self) -> Submission<'_> {
+        Submission {
+            email: &self.email,
+            honeypot: self.website.as_deref(),
+            form_token: self.form_token.as_deref(),
+            proof_of_work: self.proof_of_work.as_deref(),
+        }
+    }
+}
+
+/// The JSON answer to a subscription request. It is the same whether or not a
+/// confirmation email was sent.
+#[derive(serde::Serialize, ToSchema)]
+pub struct SubscriptionAccepted {
+    message: &'static str,
 }
 
 impl TryFrom<FormData> for NewSubscriber {
-    type Error = anyhow::Error;
+    type Error = FieldErrors;
 
     fn try_from(value: FormData) -> Result<Self, Self::Error> {
-        let name = SubscriberName::parse(value.name)?;
-        let email = SubscriberEmail::parse(value.email)?;
-        Ok(NewSubscriber { email, name })
+        let name = SubscriberName::parse(value.name).map_err(|e| FieldError::new("name", e));
+        let email = SubscriberEmail::parse(value.email).map_err(|e| FieldError::new("email", e));
+        match (name, email) {
+            (Ok(name), Ok(email)) => Ok(NewSubscriber { email, name }),
+            (name, email) => Err(FieldErrors(
+                [name.err(), email.err()].into_iter().flatten().collect(),
+            )),
+        }
     }
 }
 
+/// The subscription request body, read as JSON or as an urlencoded form
+/// depending on its `Content-Type`.
+pub struct SubscriptionBody(pub FormData);
+
+impl FromRequest for SubscriptionBody {
+    type Error = SubscribeError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let req = req.clone();
+        let mut payload = payload.take();
+
+        Box::pin(async move {
+            let mime = req.mime_type().ok().flatten();
+            let body = match mime.as_ref().map(|m| m.essence_str()) {
+                Some("application/json") => web::Json::<FormData>::from_request(&req, &mut payload)
+                    .await
+                    .map(web::Json::into_inner),
+                Some("application/x-www-form-urlencoded") => {
+                    web::Form::<FormData>::from_request(&req, &mut payload)
+                        .await
+                        .map(web::Form::into_inner)
+                }
+                _ => return Err(SubscribeError::UnsupportedMediaType(mime)),
+            };
+            body.map(SubscriptionBody)
+                .map_err(SubscribeError::MalformedBody)
+        })
+    }
+}
+
+/// Whether the client prefers a JSON response over an empty one.
+fn wants_json(request: &HttpRequest) -> bool {
+    Accept::parse(request)
+        .ok()
+        .and_then(|accept| accept.ranked().into_iter().next())
+        .is_some_and(|mime| mime == mime::APPLICATION_JSON)
+}
+
+#[derive(thiserror::Error)]
+pub enum SubscribeError {
+    #[error("the subscription request is invalid: {0}")]
+    ValidationError(FieldErrors),
+    #[error("the request body could not be parsed")]
+    MalformedBody(#[source] actix_web::Error),
+    #[error("unsupported content type {}", .0.as_ref().map_or("(none)", |m| m.essence_str()))]
+    UnsupportedMediaType(Option<mime::Mime>),
+    #[error(transparent)]
+    InvalidIdempotencyKey(#[from] IdempotencyKeyError),
+    #[error("too many subscription requests for this email address")]
+    RateLimited { retry_after: Duration },
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl fmt::Debug for SubscribeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for SubscribeError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::ValidationError(_) | Self::MalformedBody(_) | Self::InvalidIdempotencyKey(_) => {
+                StatusCode::BAD_REQUEST
+            }
+            Self::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Self::RateLimited { .. } => StatusCode::TOO_MANY_REQUESTS,
+            Self::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            Self::ValidationError(errors) => ProblemDetails::new(
+                self.status_code(),
+                "One or more fields of the subscription request are invalid.",
+            )
+            .with_errors(&errors.0),
+            Self::MalformedBody(e) => ProblemDetails::new(self.status_code(), e),
+            Self::UnsupportedMediaType(_) => ProblemDetails::new(
+                self.status_code(),
+                "Subscriptions must be sent as application/json or \
+                application/x-www-form-urlencoded.",
+            ),
+            Self::InvalidIdempotencyKey(e) => ProblemDetails::new(self.status_code(), e),
+            Self::RateLimited { retry_after } => return too_many_requests(*retry_after),
+            // The cause is logged, but is none of the client's business.
+            Self::UnexpectedError(_) => ProblemDetails::new(
+                self.status_code(),
+                "The subscription could not be processed, please try again later.",
+            ),
+        }
+        .into_response()
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/subscriptions",
+    tag = "subscriptions",
+    request_body(
+        content = FormData,
+        content_type = "application/json",
+        description = "Also accepted as application/x-www-form-urlencoded.",
+    ),
+    params(("Idempotency-Key" = Option<String>, Header, description = "Replays the saved response for a repeated key.")),
+    responses(
+        (status = 200, description = "The subscription is pending confirmation. The body is only sent when JSON is preferred by `Accept`.", body = SubscriptionAccepted),
+        (status = 400, description = "The request is invalid.", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 415, description = "The body is neither JSON nor a form.", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 422, description = "The idempotency key was already used for a different request.", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 429, description = "Too many signups from this client or for this email. `Retry-After` says when to try again.", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 500, description = "The subscription could not be processed.", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+)]
+#[allow(clippy::too_many_arguments)]
 #[tracing::instrument(
     name = "Adding a new subscriber",
-    skip(form, db_pool, email_client),
+    skip(
+        body,
+        db_pool,
+        email_client,
+        base_url,
+        token_ttl,
+        rate_limiter,
+        bot_protection,
+        email_policy,
+        request
+    ),
     fields(
-        subscriber_email=%form.email,
-        subscriber_name=%form.name,
+        subscriber_email=%body.0.email,
+        subscriber_name=%body.0.name,
     ),
 )]
 pub async fn subscribe(
-    form: web::Form<FormData>,
+    body: SubscriptionBody,
     db_pool: web::Data<PgPool>,
     email_client: web::Data<EmailClient>,
-) -> HttpResponse {
-    let subscriber = match form.0.try_into() {
-        Ok(sub) => sub,
-        Err(_) => return HttpResponse::BadRequest().finish(),
+    base_url: web::Data<ApplicationBaseUrl>,
+    token_ttl: web::Data<ConfirmationTokenTtl>,
+    rate_limiter: Option<web::Data<RateLimiter>>,
+    bot_protection: web::Data<BotProtection>,
+    email_policy: web::Data<EmailPolicy>,
+    request: HttpRequest,
+) -> Result<HttpResponse, SubscribeError> {
+    if let Err(e) = bot_protection.check(&body.0.submission()) {
+        return Ok(dropped_as_bot(&request, e));
+    }
+
+    let form_token = body.0.form_token.clone().filter(|t| !t.is_empty());
+    let fingerprint =
+        RequestFingerprint::new(&request, &body.0).context("Failed to fingerprint the request")?;
+    let subscriber: NewSubscriber = body.0.try_into().map_err(SubscribeError::ValidationError)?;
+    email_policy.check(&subscriber.email).map_err(|e| {
+        SubscribeError::ValidationError(FieldErrors(vec![FieldError::new("email", e)]))
+    })?;
+    let idempotency_key = IdempotencyKey::from_headers(request.headers())?;
+
+    let owner = idempotency_owner(&request, rate_limiter.as_ref().map(|r| r.get_ref()));
+
+    let mut txn = match begin_request(&db_pool, idempotency_key.as_ref(), owner, &fingerprint)
+        .await
+        .context("Failed to start processing the request")?
+    {
+        NextAction::StartProcessing(t) => t,
+        NextAction::ReturnSavedResponse(saved_response) => return Ok(saved_response),
     };
 
-    let token = match insert_subscriber(&subscriber, &db_pool).await {
-        Ok(t) => t,
-        Err(_) => return HttpResponse::InternalServerError().finish(),
+    // Checked once the request is known to run, so replays of a saved
+    // response don't use up the quota. Returning drops the transaction,
+    // which releases the idempotency key again.
+    if let Some(rate_limiter) = &rate_limiter {
+        if let Decision::Limited { retry_after } = rate_limiter.check_email(&subscriber.email).await
+        {
+            return Err(SubscribeError::RateLimited { retry_after });
+        }
+    }
+
+    if let Some(form_token) = form_token.filter(|_| bot_protection.enabled) {
+        if !bot_protection
+            .spend_form_token(&mut txn, &form_token)
+            .await
+            .context("Failed to spend the form token")?
+        {
+            return Ok(dropped_as_bot(&request, BotCheckError::ReplayedFormToken));
+        }
+    }
+
+    let subscriber_id = register_subscriber(&mut txn, &subscriber)
+        .await
+        .context("Failed to register the subscriber")?;
+
+    // Already confirmed subscribers get the same response as everyone else so
+    // that the endpoint can't be used to find out who is on the list.
+    if let Some(subscriber_id) = subscriber_id {
+        let token = store_token(&mut txn, subscriber_id, token_ttl.0)
+            .await
+            .context("Failed to store the confirmation token")?;
+        send_confirmation_email(&email_client, subscriber, &base_url.0, token.as_ref())
+            .await
+            .context("Failed to send the confirmation email")?;
+        METRICS.signups_total.inc();
+    }
+
+    let response = complete_request(txn, idempotency_key.as_ref(), owner, accepted(&request))
+        .await
+        .context("Failed to complete the request")?;
+    Ok(response)
+}
+
+/// Bots get the answer a person would, so they can't tell they were caught.
+fn dropped_as_bot(request: &HttpRequest, e: BotCheckError) -> HttpResponse {
+    tracing::info!(
+        reason = e.reason(),
+        "Dropped a signup taken for a bot: {}",
+        e
+    );
+    METRICS
+        .signups_rejected_total
+        .with_label_values(&[e.reason()])
+        .inc();
+    accepted(request)
+}
+
+/// Anonymous idempotency keys belong to the client's address, as resolved by
+/// the rate limiter when it knows the trusted proxies.
+fn idempotency_owner(request: &HttpRequest, rate_limiter: Option<&RateLimiter>) -> Uuid {
+    let peer = match request.peer_addr() {
+        Some(addr) => addr.ip(),
+        None => return ANONYMOUS_USER_ID,
+    };
+    let client = match rate_limiter {
+        Some(limiter) => limiter.client_ip(peer, request.headers()),
+        None => peer,
     };
+    anonymous_user_id(client)
+}
+
+/// The same answer whether or not an email went out.
+fn accepted(request: &HttpRequest) -> HttpResponse {
+    if wants_json(request) {
+        HttpResponse::Ok().json(SubscriptionAccepted {
+            message: "Check your inbox to confirm your subscription.",
+        })
+    } else {
+        HttpResponse::Ok().finish()
+    }
+}
+
+#[tracing::instrument(
+    name = "Sending a confirmation email to a new subscriber",
+    skip(email_client, subscriber, base_url, subscription_token)
+)]
+pub async fn send_confirmation_email(
+    email_client: &EmailClient,
+    subscriber: NewSubscriber,
+    base_url: &str,
+    subscription_token: &str,
+) -> Result<(), anyhow::Error> {
+    let confirmation_link = format!(
+        "{}/subscriptions/confirm?subscription_token={}",
+        base_url, subscription_token
+    );
+    let html_body = format!(
+        "<p>Welcome to our newsletter, {}!</p>\
+        <p>Click <a href=\"{}\">here</a> to confirm your subscription.</p>",
+        htmlescape::encode_minimal(subscriber.name.as_ref()),
+        confirmation_link
+    );
+    let text_body = format!(
+        "Welcome to our newsletter, {}!\nVisit {} to confirm your subscription.",
+        subscriber.name.as_ref(),
+        confirmation_link
+    );
 
-    if email_client
-        .send_email(subscriber.email, "hello", &token, &token)
+    email_client
+        .send_email(
+            subscriber.email,
+            "Please confirm your subscription",
+            &html_body,
+            &text_body,
+        )
         .await
-        .is_err()
-    {
-        return HttpResponse::InternalServerError().finish();
+}
+
+/// Records the signup and returns the id of the subscriber if they need to
+/// (re)confirm their address, or `None` if they are already confirmed.
+///
+/// A pending subscriber gets a fresh confirmation token and an unsubscribed one
+/// starts over with a new double opt-in.
+#[tracing::instrument(name = "Registering a subscriber", skip(txn, subscriber))]
+pub async fn register_subscriber(
+    txn: &mut Transaction<'_, Postgres>,
+    subscriber: &NewSubscriber,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    if let Some(subscriber_id) = insert_subscriber(txn, subscriber).await? {
+        return Ok(Some(subscriber_id));
     }
 
-    HttpResponse::Ok().finish()
+    let existing = sqlx::query!(
+        r#"
+        SELECT id, status
+        FROM subscriptions
+        WHERE email = $1
+        FOR UPDATE
+        "#,
+        subscriber.email.as_ref(),
+    )
+    .fetch_one(&mut *txn)
+    .await?;
+
+    match existing.status.as_str() {
+        "confirmed" => Ok(None),
+        "unsubscribed" => {
+            resubscribe(txn, existing.id, subscriber).await?;
+            Ok(Some(existing.id))
+        }
+        _ => Ok(Some(existing.id)),
+    }
 }
 
 #[tracing::instrument(
     name = "Inserting a new subscriber into the database",
-    skip(subscriber, db_pool)
+    skip(txn, subscriber)
 )]
-pub async fn insert_subscriber(
+async fn insert_subscriber(
+    txn: &mut Transaction<'_, Postgres>,
     subscriber: &NewSubscriber,
-    db_pool: &web::Data<PgPool>,
-) -> Result<String, sqlx::Error> {
-    let mut txn = db_pool.begin().await?;
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let inserted = sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+        VALUES($1, $2, $3, $4, 'pending_confirmation')
+        ON CONFLICT (email) DO NOTHING
+        RETURNING id
+        "#,
+        Uuid::new_v4(),
+        subscriber.email.as_ref(),
+        subscriber.name.as_ref(),
+        Utc::now(),
+    )
+    .fetch_optional(&mut *txn)
+    .await?;
 
-    let subscriber_id = Uuid::new_v4();
-    let confirmation_token = format!("{}", Uuid::new_v4());
+    Ok(inserted.map(|r| r.id))
+}
 
+#[tracing::instrument(name = "Resubscribing a subscriber", skip(txn, subscriber))]
+async fn resubscribe(
+    txn: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    subscriber: &NewSubscriber,
+) -> Result<(), sqlx::Error> {
     sqlx::query!(
         r#"
-        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
-        VALUES($1, $2, $3, $4, 'pending_confirmation')
+        UPDATE subscriptions
+        SET name = $2, subscribed_at = $3, status = 'pending_confirmation'
+        WHERE id = $1
         "#,
         subscriber_id,
-        subscriber.email.as_ref(),
         subscriber.name.as_ref(),
         Utc::now(),
     )
-    .execute(&mut txn)
-    .await
-    .map_err(|e| {
-        tracing::error!("Failed to execute query: {:?}", e);
-        e
-    })?;
+    .execute(&mut *txn)
+    .await?;
+
+    Ok(())
+}
+
+#[tracing::instrument(name = "Storing a subscription token", skip(txn))]
+async fn store_token(
+    txn: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    ttl: std::time::Duration,
+) -> Result<SubscriptionToken, sqlx::Error> {
+    let token = SubscriptionToken::generate();
 
     sqlx::query!(
         r#"
-        INSERT INTO subscription_tokens (subscription_token, subscriber_id)
-        VALUES($1, $2)
+        INSERT INTO subscription_tokens (
+            subscription_token_hash, subscriber_id, created_at, expires_at
+        )
+        VALUES($1, $2, now(), now() + make_interval(secs => $3))
         "#,
-        confirmation_token,
+        token.hash(),
         subscriber_id,
+        ttl.as_secs_f64(),
     )
-    .execute(&mut txn)
-    .await
-    .map_err(|e| {
-        tracing::error!("Failed to execute query: {:?}", e);
-        e
-    })?;
+    .execute(&mut *txn)
+    .await?;
 
-    txn.commit().await?;
-    Ok(confirmation_token)
+    Ok(token)
 }
@@ -0,0 +1,35 @@
+use actix_web::{
+    http::header::{CacheControl, CacheDirective},
+    web, HttpResponse,
+};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::bot_protection::BotProtection;
+
+/// What the signup form needs before it can be submitted.
+#[derive(Serialize, ToSchema)]
+pub struct FormChallenge {
+    /// Sent back as `form_token` with the signup.
+    form_token: String,
+    /// Leading zero bits that `SHA-256(form_token:email:proof_of_work)` must
+    /// have; 0 when no proof of work is required.
+    proof_of_work_difficulty: u8,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/subscriptions/challenge",
+    tag = "subscriptions",
+    responses(
+        (status = 200, description = "A fresh form token, to fetch when the signup form loads.", body = FormChallenge),
+    ),
+)]
+pub async fn subscription_challenge(bot_protection: web::Data<BotProtection>) -> HttpResponse {
+    HttpResponse::Ok()
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .json(FormChallenge {
+            form_token: bot_protection.form_token(),
+            proof_of_work_difficulty: bot_protection.proof_of_work_difficulty,
+        })
+}
@@ -1,45 +1,185 @@
-use actix_web::{web, HttpResponse};
+use std::fmt;
+
+use actix_web::{http::StatusCode, web, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::Utc;
 use serde::Deserialize;
-use sqlx::PgPool;
+use sqlx::{PgPool, Postgres, Transaction};
+use utoipa::IntoParams;
+use uuid::Uuid;
+
+use crate::{
+    domain::hash_token,
+    metrics::METRICS,
+    routes::{error_chain_fmt, FieldError, ProblemDetails},
+};
 
-#[derive(Deserialize)]
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct Parameters {
-    token: String,
+    /// The token from the confirmation link.
+    #[param(required = true)]
+    subscription_token: Option<String>,
+}
+
+struct ConsumedToken {
+    subscriber_id: Uuid,
+    expired: bool,
+}
+
+#[derive(thiserror::Error)]
+pub enum ConfirmationError {
+    #[error("the subscription token is missing")]
+    MissingToken,
+    #[error("the subscription token is unknown or has already been used")]
+    UnknownToken,
+    #[error("the subscription token has expired")]
+    ExpiredToken,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl fmt::Debug for ConfirmationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        error_chain_fmt(self, f)
+    }
 }
 
+impl ResponseError for ConfirmationError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::MissingToken => StatusCode::BAD_REQUEST,
+            Self::UnknownToken => StatusCode::NOT_FOUND,
+            Self::ExpiredToken => StatusCode::GONE,
+            Self::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            Self::MissingToken => {
+                ProblemDetails::new(self.status_code(), "The confirmation link is incomplete.")
+                    .with_errors(&[FieldError::new("subscription_token", "is required")])
+            }
+            Self::UnknownToken | Self::ExpiredToken => {
+                ProblemDetails::new(self.status_code(), self)
+            }
+            Self::UnexpectedError(_) => ProblemDetails::new(
+                self.status_code(),
+                "The subscription could not be confirmed, please try again later.",
+            ),
+        }
+        .into_response()
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/subscriptions/confirm",
+    tag = "subscriptions",
+    params(Parameters),
+    responses(
+        (status = 200, description = "The subscription is confirmed."),
+        (status = 400, description = "The token is missing.", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "The token is unknown or has already been used.", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 410, description = "The token has expired.", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 429, description = "Too many confirmations from this client. `Retry-After` says when to try again.", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 500, description = "The subscription could not be confirmed.", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+)]
 #[tracing::instrument(
     name = "Confirming subscriber",
-    skip(db_pool, token),
-    fields(
-        token=%token.token,
-    ),
+    skip(db_pool, parameters),
+    fields(subscriber_id = tracing::field::Empty)
 )]
 pub async fn confirm_subscription(
     db_pool: web::Data<PgPool>,
-    token: web::Query<Parameters>,
-) -> HttpResponse {
-    let t = token.into_inner();
+    parameters: web::Query<Parameters>,
+) -> Result<HttpResponse, ConfirmationError> {
+    let subscription_token = parameters
+        .subscription_token
+        .as_deref()
+        .ok_or(ConfirmationError::MissingToken)?;
 
-    match sqlx::query!(
+    let mut txn = db_pool
+        .begin()
+        .await
+        .context("Failed to start a transaction")?;
+
+    let token = consume_token(&mut txn, subscription_token)
+        .await
+        .context("Failed to consume the subscription token")?
+        .ok_or(ConfirmationError::UnknownToken)?;
+    tracing::Span::current().record(
+        "subscriber_id",
+        tracing::field::display(token.subscriber_id),
+    );
+
+    // Expired tokens are consumed too, so they stop cluttering the table and a
+    // second attempt gets the same answer as any other used token.
+    if !token.expired {
+        confirm_subscriber(&mut txn, token.subscriber_id)
+            .await
+            .context("Failed to mark the subscriber as confirmed")?;
+    }
+    txn.commit()
+        .await
+        .context("Failed to commit the transaction")?;
+
+    if token.expired {
+        return Err(ConfirmationError::ExpiredToken);
+    }
+    METRICS.confirmations_total.inc();
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Deletes the token so it can only be used once, returning who it was issued
+/// for and whether it had already expired.
+#[tracing::instrument(name = "Consuming a subscription token", skip_all)]
+async fn consume_token(
+    txn: &mut Transaction<'_, Postgres>,
+    subscription_token: &str,
+) -> Result<Option<ConsumedToken>, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        DELETE FROM subscription_tokens
+        WHERE subscription_token_hash = $1
+        RETURNING subscriber_id, expires_at
+        "#,
+        hash_token(subscription_token),
+    )
+    .fetch_optional(&mut *txn)
+    .await?;
+
+    Ok(row.map(|r| ConsumedToken {
+        subscriber_id: r.subscriber_id,
+        expired: r.expires_at <= Utc::now(),
+    }))
+}
+
+#[tracing::instrument(name = "Marking subscriber as confirmed", skip(txn))]
+async fn confirm_subscriber(
+    txn: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
         r#"
         UPDATE subscriptions
         SET status = 'confirmed'
-        WHERE id IN (
-            SELECT subscriber_id
-            FROM subscription_tokens
-            WHERE subscription_token = $1
-        )
-        RETURNING id;
+        WHERE id = $1
         "#,
-        t.token,
+        subscriber_id,
     )
-    .fetch_one(db_pool.get_ref())
-    .await
-    {
-        Ok(_) => HttpResponse::Ok().finish(),
-        Err(e) => match e {
-            sqlx::Error::RowNotFound => HttpResponse::NotFound().finish(),
-            _ => HttpResponse::InternalServerError().finish(),
-        },
-    }
+    .execute(&mut *txn)
+    .await?;
+
+    // Any other links sent to the subscriber are no longer needed.
+    sqlx::query!(
+        "DELETE FROM subscription_tokens WHERE subscriber_id = $1",
+        subscriber_id,
+    )
+    .execute(&mut *txn)
+    .await?;
+
+    Ok(())
 }
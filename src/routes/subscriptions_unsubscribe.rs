@@ -0,0 +1,167 @@
+use std::fmt;
+
+use actix_web::{
+    http::{header::ContentType, StatusCode},
+    web, HttpResponse, ResponseError,
+};
+use anyhow::Context;
+use serde::Deserialize;
+use sqlx::PgPool;
+use utoipa::IntoParams;
+
+use crate::{
+    routes::{error_chain_fmt, FieldError, ProblemDetails},
+    unsubscribe::{UnsubscribeLinks, UnsubscribeTokenError},
+};
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct UnsubscribeParameters {
+    /// The signed token from the unsubscribe link.
+    token: String,
+}
+
+/// Asks for confirmation instead of unsubscribing right away, so that link
+/// scanners following the URL don't unsubscribe anyone.
+#[tracing::instrument(
+    name = "Showing the unsubscribe form",
+    skip(parameters, unsubscribe_links)
+)]
+pub async fn unsubscribe_form(
+    parameters: web::Query<UnsubscribeParameters>,
+    unsubscribe_links: web::Data<UnsubscribeLinks>,
+) -> Result<HttpResponse, UnsubscribeError> {
+    unsubscribe_links
+        .verify(&parameters.token)
+        .map_err(UnsubscribeError::InvalidToken)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+  <head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8" />
+    <title>Unsubscribe</title>
+  </head>
+  <body>
+    <form action="/subscriptions/unsubscribe?token={}" method="post">
+      <input type="hidden" name="List-Unsubscribe" value="One-Click" />
+      <button type="submit">Unsubscribe</button>
+    </form>
+  </body>
+</html>"#,
+            htmlescape::encode_attribute(&parameters.token)
+        )))
+}
+
+#[derive(thiserror::Error)]
+pub enum UnsubscribeError {
+    #[error("the unsubscribe token is invalid")]
+    InvalidToken(#[source] UnsubscribeTokenError),
+    #[error("the subscriber no longer exists")]
+    UnknownSubscriber,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl fmt::Debug for UnsubscribeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for UnsubscribeError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::InvalidToken(_) => StatusCode::BAD_REQUEST,
+            Self::UnknownSubscriber => StatusCode::NOT_FOUND,
+            Self::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            Self::InvalidToken(_) => {
+                ProblemDetails::new(self.status_code(), "The unsubscribe link is invalid.")
+                    .with_errors(&[FieldError::new("token", "is invalid")])
+            }
+            Self::UnknownSubscriber => ProblemDetails::new(self.status_code(), self),
+            Self::UnexpectedError(_) => ProblemDetails::new(
+                self.status_code(),
+                "The subscriber could not be unsubscribed, please try again later.",
+            ),
+        }
+        .into_response()
+    }
+}
+
+/// Handles both the form above and RFC 8058 one-click requests sent by mail
+/// clients, which post `List-Unsubscribe=One-Click` to the same URL.
+#[tracing::instrument(
+    name = "Unsubscribing subscriber",
+    skip(parameters, db_pool, unsubscribe_links),
+    fields(subscriber_id=tracing::field::Empty),
+)]
+pub async fn unsubscribe(
+    parameters: web::Query<UnsubscribeParameters>,
+    db_pool: web::Data<PgPool>,
+    unsubscribe_links: web::Data<UnsubscribeLinks>,
+) -> Result<HttpResponse, UnsubscribeError> {
+    unsubscribe_subscriber(&parameters.token, &db_pool, &unsubscribe_links).await?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::plaintext())
+        .body("You have been unsubscribed."))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/subscriptions/unsubscribe",
+    tag = "subscribers",
+    params(UnsubscribeParameters),
+    responses(
+        (status = 200, description = "The subscriber is unsubscribed."),
+        (status = 400, description = "The token is invalid.", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "The subscriber no longer exists.", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 500, description = "The subscriber could not be unsubscribed.", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+)]
+#[tracing::instrument(
+    name = "Unsubscribing subscriber through the API",
+    skip(parameters, db_pool, unsubscribe_links),
+    fields(subscriber_id=tracing::field::Empty),
+)]
+pub async fn api_unsubscribe(
+    parameters: web::Query<UnsubscribeParameters>,
+    db_pool: web::Data<PgPool>,
+    unsubscribe_links: web::Data<UnsubscribeLinks>,
+) -> Result<HttpResponse, UnsubscribeError> {
+    unsubscribe_subscriber(&parameters.token, &db_pool, &unsubscribe_links).await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+async fn unsubscribe_subscriber(
+    token: &str,
+    db_pool: &PgPool,
+    unsubscribe_links: &UnsubscribeLinks,
+) -> Result<(), UnsubscribeError> {
+    let subscriber_id = unsubscribe_links
+        .verify(token)
+        .map_err(UnsubscribeError::InvalidToken)?;
+    tracing::Span::current().record("subscriber_id", tracing::field::display(&subscriber_id));
+
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET status = 'unsubscribed'
+        WHERE id = $1
+        RETURNING id;
+        "#,
+        subscriber_id,
+    )
+    .fetch_optional(db_pool)
+    .await
+    .context("Failed to unsubscribe the subscriber")?
+    .ok_or(UnsubscribeError::UnknownSubscriber)?;
+    Ok(())
+}
@@ -0,0 +1,182 @@
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use actix_session::storage::{LoadError, SaveError, SessionKey, SessionStore, UpdateError};
+use actix_web::cookie::time::Duration;
+use anyhow::Context;
+use chrono::Utc;
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use sqlx::PgPool;
+
+type SessionState = HashMap<String, String>;
+
+/// Expired sessions are deleted once every this many new sessions.
+const PRUNE_EVERY: u64 = 1_000;
+
+static SAVES: AtomicU64 = AtomicU64::new(0);
+
+/// Keeps session state in the `sessions` table so that every instance sharing
+/// the database sees the same sessions.
+#[derive(Clone)]
+pub struct PostgresSessionStore {
+    db_pool: PgPool,
+}
+
+impl PostgresSessionStore {
+    pub fn new(db_pool: PgPool) -> Self {
+        Self { db_pool }
+    }
+}
+
+fn generate_session_key() -> SessionKey {
+    let key: String = thread_rng()
+        .sample_iter(&Alphanumeric)
+        .map(char::from)
+        .take(64)
+        .collect();
+
+    // 64 characters is well within the cookie size limit.
+    key.try_into().unwrap()
+}
+
+#[tracing::instrument(name = "Pruning expired sessions", skip(db_pool))]
+async fn prune_expired_sessions(db_pool: &PgPool) -> Result<(), sqlx::Error> {
+    sqlx::query!("DELETE FROM sessions WHERE expires_at <= now()")
+        .execute(db_pool)
+        .await?;
+    Ok(())
+}
+
+fn expires_at(ttl: &Duration) -> chrono::DateTime<Utc> {
+    Utc::now() + chrono::Duration::seconds(ttl.whole_seconds())
+}
+
+#[async_trait::async_trait(?Send)]
+impl SessionStore for PostgresSessionStore {
+    async fn load(&self, session_key: &SessionKey) -> Result<Option<SessionState>, LoadError> {
+        let row = sqlx::query!(
+            r#"
+            SELECT state
+            FROM sessions
+            WHERE session_key = $1 AND expires_at > now()
+            "#,
+            session_key.as_ref(),
+        )
+        .fetch_optional(&self.db_pool)
+        .await
+        .context("Failed to load session state")
+        .map_err(LoadError::Other)?;
+
+        row.map(|r| serde_json::from_str(&r.state))
+            .transpose()
+            .context("Failed to deserialize session state")
+            .map_err(LoadError::Deserialization)
+    }
+
+    async fn save(
+        &self,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, SaveError> {
+        let state = serde_json::to_string(&session_state)
+            .context("Failed to serialize session state")
+            .map_err(SaveError::Serialization)?;
+        let session_key = generate_session_key();
+
+        if SAVES
+            .fetch_add(1, Ordering::Relaxed)
+            .is_multiple_of(PRUNE_EVERY)
+        {
+            prune_expired_sessions(&self.db_pool)
+                .await
+                .context("Failed to prune expired sessions")
+                .map_err(SaveError::Other)?;
+        }
+
+        sqlx::query!(
+            r#"
+            INSERT INTO sessions (session_key, state, expires_at)
+            VALUES ($1, $2, $3)
+            "#,
+            session_key.as_ref(),
+            state,
+            expires_at(ttl),
+        )
+        .execute(&self.db_pool)
+        .await
+        .context("Failed to save session state")
+        .map_err(SaveError::Other)?;
+
+        Ok(session_key)
+    }
+
+    async fn update(
+        &self,
+        session_key: SessionKey,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, UpdateError> {
+        let state = serde_json::to_string(&session_state)
+            .context("Failed to serialize session state")
+            .map_err(UpdateError::Serialization)?;
+
+        let updated = sqlx::query!(
+            r#"
+            UPDATE sessions
+            SET state = $2, expires_at = $3
+            WHERE session_key = $1
+            "#,
+            session_key.as_ref(),
+            state,
+            expires_at(ttl),
+        )
+        .execute(&self.db_pool)
+        .await
+        .context("Failed to update session state")
+        .map_err(UpdateError::Other)?;
+
+        if updated.rows_affected() == 0 {
+            // The session expired or was deleted in the meantime: start a new one.
+            return self.save(session_state, ttl).await.map_err(|e| match e {
+                SaveError::Serialization(e) => UpdateError::Serialization(e),
+                SaveError::Other(e) => UpdateError::Other(e),
+            });
+        }
+
+        Ok(session_key)
+    }
+
+    async fn update_ttl(&self, session_key: &SessionKey, ttl: &Duration) -> anyhow::Result<()> {
+        sqlx::query!(
+            r#"
+            UPDATE sessions
+            SET expires_at = $2
+            WHERE session_key = $1
+            "#,
+            session_key.as_ref(),
+            expires_at(ttl),
+        )
+        .execute(&self.db_pool)
+        .await
+        .context("Failed to update session expiry")?;
+
+        Ok(())
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> anyhow::Result<()> {
+        sqlx::query!(
+            r#"
+            DELETE FROM sessions
+            WHERE session_key = $1
+            "#,
+            session_key.as_ref(),
+        )
+        .execute(&self.db_pool)
+        .await
+        .context("Failed to delete session")?;
+
+        Ok(())
+    }
+}
@@ -1,78 +1,278 @@
-use std::net::TcpListener;
+use std::{net::TcpListener, time::Duration};
 
 use crate::{
-    configuration::Settings,
+    authentication::{create_first_user, reject_anonymous_users},
+    bot_protection::BotProtection,
+    configuration::{ConnectStrategy, DatabaseSettings, Settings},
+    domain::EmailPolicy,
     email_client::EmailClient,
-    routes::{confirm_subscription, health_check, subscribe},
+    health::ReadinessProbe,
+    issue_delivery_worker::run_worker_until_stopped,
+    metrics::{metrics, track_http_requests, MetricsBearerToken},
+    rate_limit::{limit_by_client_ip, RateLimiter},
+    routes::{
+        admin_dashboard, api_unsubscribe, confirm_subscription, get_log_level, health_check, home,
+        liveness, log_out, login, login_form, openapi_json, publish_newsletter, put_log_level,
+        readiness, subscribe, subscription_challenge, unsubscribe, unsubscribe_form, ApiDoc,
+    },
+    session_store::PostgresSessionStore,
+    unsubscribe::UnsubscribeLinks,
 };
-use actix_web::{dev::Server, web, App, HttpServer};
-use secrecy::ExposeSecret;
+use actix_session::SessionMiddleware;
+use actix_web::{
+    cookie::Key,
+    dev::{HttpServiceFactory, Server},
+    web, App, HttpServer,
+};
+use actix_web_flash_messages::{storage::CookieMessageStore, FlashMessagesFramework};
+use actix_web_lab::middleware::from_fn;
+use secrecy::{ExposeSecret, Secret};
 use sqlx::PgPool;
 use tracing_actix_web::TracingLogger;
+use utoipa::OpenApi;
 
 pub struct Application {
     port: u16,
     server: Server,
+    db_pool: PgPool,
+    email_client: EmailClient,
+    unsubscribe_links: UnsubscribeLinks,
 }
 
 impl Application {
     pub async fn build(config: Settings) -> anyhow::Result<Self> {
         let address = format!("{}:{}", config.application.host, config.application.port);
 
-        let db_pool = get_connection_pool(&config).await;
+        let db_pool = get_connection_pool(&config.database).await?;
 
-        let sender = config.email.sender()?;
-        let timeout = config.email.timeout();
-        let email_client = EmailClient::new(
-            sender,
-            config.email.base_url,
-            config.email.authorization_token,
-            timeout,
+        // A database that isn't up yet must not stop a lazily connecting
+        // instance from starting, so this is retried on the next start instead.
+        if let Some(admin) = &config.admin {
+            match create_first_user(&admin.username, &admin.password_hash, &db_pool).await {
+                Ok(true) => {
+                    tracing::info!(username = %admin.username, "Created the first administrator")
+                }
+                Ok(false) => {}
+                Err(e) => tracing::error!("Failed to create the first administrator: {:?}", e),
+            }
+        }
+
+        let readiness_probe = config.health.probe(&config.email);
+        let email_client = config.email.client()?;
+        let unsubscribe_links = UnsubscribeLinks::new(
+            config.application.base_url.clone(),
+            config.application.hmac_secret.clone(),
         );
 
+        let confirmation_token_ttl = config.application.confirmation_token_ttl();
+        let rate_limiter = config.rate_limit.limiter(&db_pool)?;
+        let bot_protection = config
+            .bot_protection
+            .protection(config.application.hmac_secret.clone());
+        let email_policy = config.email_policy.policy();
+
         let listener = TcpListener::bind(&address)?;
         let port = listener.local_addr()?.port();
-        let server = run(listener, db_pool, email_client)?;
+        let server = run(
+            listener,
+            db_pool.clone(),
+            email_client.clone(),
+            config.application.base_url,
+            config.application.hmac_secret,
+            confirmation_token_ttl,
+            readiness_probe,
+            rate_limiter,
+            bot_protection,
+            email_policy,
+            config.metrics.bearer_token,
+        )?;
 
-        Ok(Self { port, server })
+        Ok(Self {
+            port,
+            server,
+            db_pool,
+            email_client,
+            unsubscribe_links,
+        })
     }
 
     pub fn port(&self) -> u16 {
         self.port
     }
 
-    pub async fn run_until_stopped(self) -> Result<(), std::io::Error> {
+    /// Serves HTTP requests and delivers queued newsletter issues until either
+    /// of the two stops.
+    pub async fn run_until_stopped(self) -> anyhow::Result<()> {
+        let worker =
+            run_worker_until_stopped(self.db_pool, self.email_client, self.unsubscribe_links);
+
+        tokio::select! {
+            outcome = self.server => {
+                tracing::info!("HTTP server stopped");
+                outcome?;
+            }
+            outcome = worker => {
+                tracing::info!("Issue delivery worker stopped");
+                outcome?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Serves HTTP requests without draining the delivery queue in the
+    /// background, leaving delivery to explicit calls of `try_execute_task`.
+    pub async fn run_server_until_stopped(self) -> Result<(), std::io::Error> {
         self.server.await
     }
 }
 
-pub async fn get_connection_pool(config: &Settings) -> PgPool {
-    let dsn = config.database.dsn();
+/// Creates the pool according to `database.connect`: either lazily, leaving
+/// connection errors to the first query, or eagerly with bounded retries so
+/// startup can wait for a database that is still coming up.
+pub async fn get_connection_pool(config: &DatabaseSettings) -> Result<PgPool, sqlx::Error> {
+    if config.connect.strategy == ConnectStrategy::Lazy {
+        return Ok(config.pool_options().connect_lazy_with(config.with_db()));
+    }
+
+    let mut attempt = 1;
+    loop {
+        match config.pool_options().connect_with(config.with_db()).await {
+            Ok(pool) => return Ok(pool),
+            Err(e) if attempt < config.connect.max_attempts => {
+                let delay = config.connect.delay(attempt);
+                tracing::warn!(
+                    error.message = %e,
+                    attempt,
+                    "Failed to connect to the database, retrying in {:?}",
+                    delay
+                );
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// The signup endpoint, limited per client IP wherever it is mounted.
+fn subscribe_resource(path: &str) -> impl HttpServiceFactory {
+    web::resource(path)
+        .wrap(from_fn(limit_by_client_ip))
+        .route(web::post().to(subscribe))
+}
 
-    PgPool::connect(&dsn.expose_secret())
-        .await
-        .expect("Failed to connect to database")
+fn confirm_resource(path: &str) -> impl HttpServiceFactory {
+    web::resource(path)
+        .wrap(from_fn(limit_by_client_ip))
+        .route(web::get().to(confirm_subscription))
 }
 
+/// Public URL the application is reachable at, used to build links in emails.
+pub struct ApplicationBaseUrl(pub String);
+
+/// How long a subscription confirmation token can be used after it is issued.
+pub struct ConfirmationTokenTtl(pub Duration);
+
+#[allow(clippy::too_many_arguments)]
 pub fn run(
     listener: TcpListener,
     db_pool: PgPool,
     email_client: EmailClient,
+    base_url: String,
+    hmac_secret: Secret<String>,
+    confirmation_token_ttl: Duration,
+    readiness_probe: ReadinessProbe,
+    rate_limiter: Option<RateLimiter>,
+    bot_protection: BotProtection,
+    email_policy: EmailPolicy,
+    metrics_bearer_token: Option<Secret<String>>,
 ) -> Result<Server, std::io::Error> {
+    let secret_key = Key::from(hmac_secret.expose_secret().as_bytes());
+    let message_store = CookieMessageStore::builder(secret_key.clone()).build();
+    let message_framework = FlashMessagesFramework::builder(message_store).build();
+    let session_store = PostgresSessionStore::new(db_pool.clone());
+
     let db_pool = web::Data::new(db_pool);
     let email_client = web::Data::new(email_client);
+    let unsubscribe_links =
+        web::Data::new(UnsubscribeLinks::new(base_url.clone(), hmac_secret.clone()));
+    let base_url = web::Data::new(ApplicationBaseUrl(base_url));
+    let confirmation_token_ttl = web::Data::new(ConfirmationTokenTtl(confirmation_token_ttl));
+    let readiness_probe = web::Data::new(readiness_probe);
+    let openapi = web::Data::new(ApiDoc::openapi());
+    let rate_limiter = rate_limiter.map(web::Data::new);
+    let bot_protection = web::Data::new(bot_protection);
+    let email_policy = web::Data::new(email_policy);
+    let metrics_bearer_token = metrics_bearer_token.map(|t| web::Data::new(MetricsBearerToken(t)));
 
     Ok(HttpServer::new(move || {
-        App::new()
+        let app = App::new()
+            .wrap(message_framework.clone())
+            .wrap(SessionMiddleware::new(
+                session_store.clone(),
+                secret_key.clone(),
+            ))
+            .wrap(from_fn(track_http_requests))
             .wrap(TracingLogger::default())
+            .route("/", web::get().to(home))
             .route("/health", web::get().to(health_check))
-            .route("/subscriptions", web::post().to(subscribe))
+            .route("/health/live", web::get().to(liveness))
+            .route("/health/ready", web::get().to(readiness))
+            .route("/metrics", web::get().to(metrics))
+            .route("/login", web::get().to(login_form))
+            .route("/login", web::post().to(login))
+            .route("/logout", web::post().to(log_out))
+            .service(subscribe_resource("/subscriptions"))
+            .route(
+                "/subscriptions/challenge",
+                web::get().to(subscription_challenge),
+            )
+            .service(confirm_resource("/subscriptions/confirm"))
             .route(
-                "/subscriptions/confirm",
-                web::get().to(confirm_subscription),
+                "/subscriptions/unsubscribe",
+                web::get().to(unsubscribe_form),
+            )
+            .route("/subscriptions/unsubscribe", web::post().to(unsubscribe))
+            .route("/newsletters", web::post().to(publish_newsletter))
+            .service(
+                web::scope("/api/v1")
+                    .route("/openapi.json", web::get().to(openapi_json))
+                    .service(subscribe_resource("/subscriptions"))
+                    .route(
+                        "/subscriptions/challenge",
+                        web::get().to(subscription_challenge),
+                    )
+                    .service(confirm_resource("/subscriptions/confirm"))
+                    .route(
+                        "/subscriptions/unsubscribe",
+                        web::post().to(api_unsubscribe),
+                    ),
+            )
+            .service(
+                web::scope("/admin")
+                    .wrap(from_fn(reject_anonymous_users))
+                    .route("/dashboard", web::get().to(admin_dashboard))
+                    .route("/log-level", web::get().to(get_log_level))
+                    .route("/log-level", web::put().to(put_log_level)),
             )
             .app_data(db_pool.clone())
             .app_data(email_client.clone())
+            .app_data(base_url.clone())
+            .app_data(confirmation_token_ttl.clone())
+            .app_data(readiness_probe.clone())
+            .app_data(unsubscribe_links.clone())
+            .app_data(openapi.clone())
+            .app_data(bot_protection.clone())
+            .app_data(email_policy.clone());
+        let app = match &rate_limiter {
+            Some(rate_limiter) => app.app_data(rate_limiter.clone()),
+            None => app,
+        };
+        match &metrics_bearer_token {
+            Some(token) => app.app_data(token.clone()),
+            None => app,
+        }
     })
     .listen(listener)?
     .run())
@@ -0,0 +1,228 @@
+mod redaction;
+
+use std::collections::HashMap;
+
+use actix_web::rt::task::{spawn_blocking, JoinHandle};
+use once_cell::sync::OnceCell;
+use opentelemetry::{
+    global,
+    sdk::{propagation::TraceContextPropagator, trace, trace::Sampler, Resource},
+    trace::TraceError,
+    KeyValue,
+};
+use opentelemetry_otlp::WithExportConfig;
+use tracing::{dispatcher::set_global_default, Dispatch, Subscriber};
+use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
+use tracing_log::LogTracer;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{
+    fmt::MakeWriter, layer::SubscriberExt, reload, EnvFilter, Layer, Registry,
+};
+
+pub use redaction::*;
+
+use crate::configuration::{LogFormat, OtlpSettings, TelemetrySettings};
+
+type LogFilterHandle = reload::Handle<EnvFilter, Registry>;
+
+static LOG_FILTER: OnceCell<LogFilterHandle> = OnceCell::new();
+
+/// Audit events are kept whatever the rest of the filter says.
+const AUDIT_DIRECTIVE: &str = "audit=info";
+
+#[derive(thiserror::Error, Debug)]
+pub enum LogFilterError {
+    #[error("invalid log filter: {0}")]
+    Invalid(#[from] tracing_subscriber::filter::ParseError),
+    #[error("no subscriber with a reloadable log filter has been built")]
+    NotInstalled,
+    #[error("failed to reload the log filter")]
+    Reload(#[from] reload::Error),
+}
+
+/// Builds the subscriber that logs to `sink` in the configured format and,
+/// when a tracer is given, also exports spans through it. Sensitive fields are
+/// redacted before they reach either.
+///
+/// Once installed with `init_subscriber`, its filter can be changed at runtime
+/// with `set_log_filter`.
+pub fn get_subscriber<Sink>(
+    name: String,
+    env_filter: String,
+    sink: Sink,
+    settings: &TelemetrySettings,
+    tracer: Option<trace::Tracer>,
+) -> impl Subscriber + Send + Sync
+where
+    Sink: for<'a> MakeWriter<'a> + Send + Sync + 'static,
+{
+    let env_filter = EnvFilter::try_from_default_env()
+        .unwrap_or(EnvFilter::new(env_filter))
+        .add_directive(
+            AUDIT_DIRECTIVE
+                .parse()
+                .expect("The audit directive is valid"),
+        );
+    let (env_filter, handle) = reload::Layer::new(env_filter);
+    let redactor = Redactor::new(&settings.redaction);
+
+    let (bunyan_layer, pretty_layer) = match settings.format {
+        LogFormat::Bunyan => {
+            let layer = JsonStorageLayer.and_then(BunyanFormattingLayer::new(name, sink));
+            (Some(layer), None)
+        }
+        LogFormat::Pretty => {
+            let layer = tracing_subscriber::fmt::layer().with_writer(sink);
+            (None, Some(layer))
+        }
+    };
+    let otel_layer = tracer.map(|t| tracing_opentelemetry::layer().with_tracer(t));
+    let outputs = Layer::and_then(bunyan_layer, pretty_layer).and_then(otel_layer);
+
+    Registry::default()
+        .with(env_filter)
+        .with(LogFilterLayer(handle))
+        .with(RedactingLayer::new(redactor, outputs))
+}
+
+/// Carries the handle of the reloadable filter to `init_subscriber`, which
+/// finds it by downcasting.
+struct LogFilterLayer(LogFilterHandle);
+
+impl<S: Subscriber> Layer<S> for LogFilterLayer {}
+
+pub fn init_subscriber(subscriber: impl Subscriber + Send + Sync) {
+    let dispatch = Dispatch::new(subscriber);
+    if let Some(layer) = dispatch.downcast_ref::<LogFilterLayer>() {
+        let _ = LOG_FILTER.set(layer.0.clone());
+    }
+    LogTracer::init().expect("Failed to set logger");
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    set_global_default(dispatch).expect("Failed to set subscriber");
+}
+
+/// The filter directives currently in effect.
+pub fn current_log_filter() -> Result<String, LogFilterError> {
+    LOG_FILTER
+        .get()
+        .ok_or(LogFilterError::NotInstalled)?
+        .with_current(|filter| filter.to_string())
+        .map_err(LogFilterError::from)
+}
+
+/// Parses filter `directives`, in `RUST_LOG` syntax, for `set_log_filter`.
+pub fn parse_log_filter(directives: &str) -> Result<EnvFilter, LogFilterError> {
+    Ok(EnvFilter::try_new(directives)?.add_directive(AUDIT_DIRECTIVE.parse()?))
+}
+
+/// Replaces the filter in effect with `filter`.
+pub fn set_log_filter(filter: EnvFilter) -> Result<(), LogFilterError> {
+    LOG_FILTER
+        .get()
+        .ok_or(LogFilterError::NotInstalled)?
+        .reload(filter)?;
+    Ok(())
+}
+
+/// Starts a batch exporter sending spans to the OTLP collector over gRPC.
+pub fn otlp_tracer(settings: &OtlpSettings) -> Result<trace::Tracer, TraceError> {
+    opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(&settings.endpoint),
+        )
+        .with_trace_config(
+            trace::config()
+                .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+                    settings.sampling_ratio,
+                ))))
+                .with_resource(Resource::new(vec![KeyValue::new(
+                    "service.name",
+                    settings.service_name.clone(),
+                )])),
+        )
+        .install_batch(opentelemetry::runtime::TokioCurrentThread)
+}
+
+/// Flushes spans that have not been exported yet.
+pub fn shutdown_tracing() {
+    global::shutdown_tracer_provider();
+}
+
+/// W3C trace context headers for the current span, to forward on outgoing
+/// requests. Empty when spans are not being exported.
+pub fn trace_context_headers() -> HashMap<String, String> {
+    let context = tracing::Span::current().context();
+    let mut headers = HashMap::new();
+    global::get_text_map_propagator(|propagator| propagator.inject_context(&context, &mut headers));
+    headers
+}
+
+pub fn spawn_blocking_with_tracing<F, R>(f: F) -> JoinHandle<R>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    let current_span = tracing::Span::current();
+    spawn_blocking(move || current_span.in_scope(f))
+}
+
+#[cfg(test)]
+mod tests {
+    use opentelemetry::{
+        global,
+        sdk::{propagation::TraceContextPropagator, trace::TracerProvider},
+        trace::TracerProvider as _,
+    };
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::{
+        current_log_filter, get_subscriber, parse_log_filter, trace_context_headers, LogFilterError,
+    };
+    use crate::configuration::TelemetrySettings;
+
+    #[test]
+    fn only_an_installed_subscriber_provides_the_log_filter() {
+        let _subscriber = get_subscriber(
+            "test".into(),
+            "info".into(),
+            std::io::sink,
+            &TelemetrySettings::default(),
+            None,
+        );
+
+        assert!(matches!(
+            current_log_filter(),
+            Err(LogFilterError::NotInstalled)
+        ));
+    }
+
+    #[test]
+    fn audit_events_survive_any_log_filter() {
+        let filter = parse_log_filter("off").unwrap().to_string();
+
+        assert!(filter.contains("audit=info"), "{}", filter);
+    }
+
+    #[test]
+    fn trace_context_is_only_propagated_for_exported_spans() {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+
+        let headers = tracing::info_span!("untraced").in_scope(trace_context_headers);
+        assert!(headers.is_empty());
+
+        // The tracer only holds a weak reference, so the provider must outlive it.
+        let provider = TracerProvider::builder().build();
+        let tracer = provider.tracer("test");
+        let subscriber =
+            tracing_subscriber::registry().with(tracing_opentelemetry::layer().with_tracer(tracer));
+        let headers = tracing::subscriber::with_default(subscriber, || {
+            tracing::info_span!("traced").in_scope(trace_context_headers)
+        });
+
+        let traceparent = &headers["traceparent"];
+        assert!(traceparent.starts_with("00-"), "{}", traceparent);
+    }
+}
@@ -0,0 +1,570 @@
+use std::{any::TypeId, collections::HashSet, fmt, sync::Arc};
+
+use sha2::{Digest, Sha256};
+use tracing::{
+    field::{DisplayValue, Field, FieldSet, Value, ValueSet, Visit},
+    level_filters::LevelFilter,
+    span::{Attributes, Id, Record},
+    subscriber::Interest,
+    Dispatch, Event, Metadata, Subscriber,
+};
+use tracing_subscriber::{
+    layer::{Context, Layer},
+    registry::LookupSpan,
+};
+
+use crate::configuration::{RedactionPolicy, RedactionSettings};
+
+/// Decides what is logged in place of sensitive span and event fields.
+#[derive(Clone, Debug)]
+pub struct Redactor {
+    policy: RedactionPolicy,
+    fields: Arc<HashSet<String>>,
+}
+
+impl Redactor {
+    pub fn new(settings: &RedactionSettings) -> Self {
+        Self {
+            policy: settings.policy,
+            fields: Arc::new(settings.fields.iter().cloned().collect()),
+        }
+    }
+
+    /// Whether `redact_named` may change the field, to skip the others cheaply.
+    fn may_redact_named(&self, name: &str) -> bool {
+        name == REQUEST_TARGET || self.fields.contains(name)
+    }
+
+    fn redact_named(&self, name: &str, value: &str) -> Redacted {
+        if self.fields.contains(name) {
+            match self.redact(value) {
+                Some(redacted) => Redacted::Replaced(redacted),
+                None => Redacted::Dropped,
+            }
+        } else if name == REQUEST_TARGET {
+            match self.redact_query(value) {
+                Some(target) => Redacted::Replaced(target),
+                None => Redacted::Unchanged,
+            }
+        } else {
+            Redacted::Unchanged
+        }
+    }
+
+    /// What to log instead of `value`, or `None` if the field must be left out.
+    pub fn redact(&self, value: &str) -> Option<String> {
+        match self.policy {
+            RedactionPolicy::Hash => {
+                let digest = format!("{:x}", Sha256::digest(value.as_bytes()));
+                Some(format!("sha256:{}", &digest[..16]))
+            }
+            RedactionPolicy::Mask => Some(mask(value)),
+            RedactionPolicy::Drop => None,
+        }
+    }
+
+    /// Applies the policy to query parameters named like a redacted field, such
+    /// as the token in `/subscriptions/confirm?subscription_token=...`.
+    fn redact_query(&self, target: &str) -> Option<String> {
+        let (path, query) = target.split_once('?')?;
+        let mut changed = false;
+        let pairs: Vec<String> = query
+            .split('&')
+            .filter_map(|pair| {
+                let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+                if !self.fields.contains(key) {
+                    return Some(pair.to_string());
+                }
+                changed = true;
+                self.redact(value).map(|v| format!("{}={}", key, v))
+            })
+            .collect();
+
+        changed.then(|| format!("{}?{}", path, pairs.join("&")))
+    }
+}
+
+/// Field recorded by `TracingLogger` with the request path and query string.
+const REQUEST_TARGET: &str = "http.target";
+
+enum Redacted {
+    Unchanged,
+    Replaced(String),
+    Dropped,
+}
+
+/// Keeps the first character and, for email addresses, the domain.
+fn mask(value: &str) -> String {
+    let (local, domain) = match value.rsplit_once('@') {
+        Some((local, domain)) => (local, Some(domain)),
+        None => (value, None),
+    };
+    let mut masked: String = local.chars().take(1).collect();
+    masked.push_str("***");
+    if let Some(domain) = domain {
+        masked.push('@');
+        masked.push_str(domain);
+    }
+    masked
+}
+
+/// Applies the redaction policy to span and event fields before handing them
+/// to `inner`, so every output behind it, whether JSON, pretty or OTLP, only
+/// ever sees the redacted values. Callsites without a sensitive field are
+/// passed through untouched.
+pub struct RedactingLayer<L> {
+    redactor: Redactor,
+    inner: L,
+}
+
+impl<L> RedactingLayer<L> {
+    pub fn new(redactor: Redactor, inner: L) -> Self {
+        Self { redactor, inner }
+    }
+}
+
+impl<S, L> Layer<S> for RedactingLayer<L>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    L: Layer<S>,
+{
+    fn on_register_dispatch(&self, subscriber: &Dispatch) {
+        self.inner.on_register_dispatch(subscriber)
+    }
+
+    fn on_layer(&mut self, subscriber: &mut S) {
+        self.inner.on_layer(subscriber)
+    }
+
+    fn register_callsite(&self, metadata: &'static Metadata<'static>) -> Interest {
+        self.inner.register_callsite(metadata)
+    }
+
+    fn enabled(&self, metadata: &Metadata<'_>, ctx: Context<'_, S>) -> bool {
+        self.inner.enabled(metadata, ctx)
+    }
+
+    fn max_level_hint(&self) -> Option<LevelFilter> {
+        self.inner.max_level_hint()
+    }
+
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let metadata = attrs.metadata();
+        let values = match self
+            .redactor
+            .redact_values(metadata.fields(), |v| attrs.record(v))
+        {
+            Some(values) => values,
+            None => return self.inner.on_new_span(attrs, id, ctx),
+        };
+        with_value_set(metadata.fields(), &values, |values| {
+            let redacted = if attrs.is_root() {
+                Attributes::new_root(metadata, values)
+            } else if let Some(parent) = attrs.parent() {
+                Attributes::child_of(parent.clone(), metadata, values)
+            } else {
+                Attributes::new(metadata, values)
+            };
+            self.inner.on_new_span(&redacted, id, ctx)
+        })
+    }
+
+    fn on_record(&self, span: &Id, record: &Record<'_>, ctx: Context<'_, S>) {
+        let fields = match ctx.metadata(span) {
+            Some(metadata) => metadata.fields(),
+            None => return self.inner.on_record(span, record, ctx),
+        };
+        let values = match self.redactor.redact_values(fields, |v| record.record(v)) {
+            Some(values) => values,
+            None => return self.inner.on_record(span, record, ctx),
+        };
+        with_value_set(fields, &values, |values| {
+            self.inner.on_record(span, &Record::new(values), ctx)
+        })
+    }
+
+    fn on_follows_from(&self, span: &Id, follows: &Id, ctx: Context<'_, S>) {
+        self.inner.on_follows_from(span, follows, ctx)
+    }
+
+    fn event_enabled(&self, event: &Event<'_>, ctx: Context<'_, S>) -> bool {
+        self.inner.event_enabled(event, ctx)
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        let values = match self
+            .redactor
+            .redact_values(metadata.fields(), |v| event.record(v))
+        {
+            Some(values) => values,
+            None => return self.inner.on_event(event, ctx),
+        };
+        with_value_set(metadata.fields(), &values, |values| {
+            let redacted = if event.is_root() {
+                Event::new_child_of(None, metadata, values)
+            } else if let Some(parent) = event.parent() {
+                Event::new_child_of(parent.clone(), metadata, values)
+            } else {
+                Event::new(metadata, values)
+            };
+            self.inner.on_event(&redacted, ctx)
+        })
+    }
+
+    fn on_enter(&self, id: &Id, ctx: Context<'_, S>) {
+        self.inner.on_enter(id, ctx)
+    }
+
+    fn on_exit(&self, id: &Id, ctx: Context<'_, S>) {
+        self.inner.on_exit(id, ctx)
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        self.inner.on_close(id, ctx)
+    }
+
+    fn on_id_change(&self, old: &Id, new: &Id, ctx: Context<'_, S>) {
+        self.inner.on_id_change(old, new, ctx)
+    }
+
+    // Lets `OpenTelemetrySpanExt` find the OpenTelemetry layer behind this one.
+    unsafe fn downcast_raw(&self, id: TypeId) -> Option<*const ()> {
+        if id == TypeId::of::<Self>() {
+            Some(self as *const Self as *const ())
+        } else {
+            self.inner.downcast_raw(id)
+        }
+    }
+}
+
+impl Redactor {
+    /// Copies of the values `record` visits, with the policy applied, or `None`
+    /// when none of them has to change.
+    fn redact_values(
+        &self,
+        fields: &FieldSet,
+        record: impl FnOnce(&mut dyn Visit),
+    ) -> Option<Vec<(Field, FieldValue)>> {
+        if !fields.iter().any(|f| self.may_redact_named(f.name())) {
+            return None;
+        }
+        let mut captured = CapturedValues::default();
+        record(&mut captured);
+
+        let mut changed = false;
+        let values = captured
+            .0
+            .into_iter()
+            .filter_map(|(field, value)| {
+                if !self.may_redact_named(field.name()) {
+                    return Some((field, value));
+                }
+                match self.redact_named(field.name(), &value.to_text()) {
+                    Redacted::Unchanged => Some((field, value)),
+                    Redacted::Replaced(redacted) => {
+                        changed = true;
+                        Some((field, FieldValue::Str(redacted)))
+                    }
+                    Redacted::Dropped => {
+                        changed = true;
+                        None
+                    }
+                }
+            })
+            .collect();
+        changed.then_some(values)
+    }
+}
+
+/// The most values a `ValueSet` can hold, as enforced by the `tracing` macros.
+const MAX_VALUES: usize = 32;
+
+/// Calls `f` with `values` as a `ValueSet` of `fields`. A `ValueSet` can only
+/// be built from an array, so the unused slots are padded with empty values.
+fn with_value_set<R>(
+    fields: &FieldSet,
+    values: &[(Field, FieldValue)],
+    f: impl FnOnce(&ValueSet<'_>) -> R,
+) -> R {
+    let first = match values.first() {
+        Some((field, _)) => field,
+        None => {
+            let empty: [(&Field, Option<&dyn Value>); 0] = [];
+            return f(&fields.value_set(&empty));
+        }
+    };
+    let slots: [(&Field, Option<&dyn Value>); MAX_VALUES] = std::array::from_fn(|i| {
+        values.get(i).map_or((first, None), |(field, value)| {
+            (field, Some(value.as_value()))
+        })
+    });
+    f(&fields.value_set(&slots))
+}
+
+/// An owned copy of a recorded value, as `Value` can't be implemented outside
+/// of `tracing`.
+enum FieldValue {
+    I64(i64),
+    U64(u64),
+    I128(i128),
+    U128(u128),
+    F64(f64),
+    Bool(bool),
+    Str(String),
+    /// Values recorded through `Debug` or `Error`, already formatted.
+    Formatted(DisplayValue<String>),
+}
+
+impl FieldValue {
+    fn as_value(&self) -> &dyn Value {
+        match self {
+            Self::I64(v) => v,
+            Self::U64(v) => v,
+            Self::I128(v) => v,
+            Self::U128(v) => v,
+            Self::F64(v) => v,
+            Self::Bool(v) => v,
+            Self::Str(v) => v,
+            Self::Formatted(v) => v,
+        }
+    }
+
+    fn to_text(&self) -> String {
+        match self {
+            Self::I64(v) => v.to_string(),
+            Self::U64(v) => v.to_string(),
+            Self::I128(v) => v.to_string(),
+            Self::U128(v) => v.to_string(),
+            Self::F64(v) => v.to_string(),
+            Self::Bool(v) => v.to_string(),
+            Self::Str(v) => v.clone(),
+            Self::Formatted(v) => format!("{:?}", v),
+        }
+    }
+}
+
+#[derive(Default)]
+struct CapturedValues(Vec<(Field, FieldValue)>);
+
+impl Visit for CapturedValues {
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.push((field.clone(), FieldValue::I64(value)));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.push((field.clone(), FieldValue::U64(value)));
+    }
+
+    fn record_i128(&mut self, field: &Field, value: i128) {
+        self.0.push((field.clone(), FieldValue::I128(value)));
+    }
+
+    fn record_u128(&mut self, field: &Field, value: u128) {
+        self.0.push((field.clone(), FieldValue::U128(value)));
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.push((field.clone(), FieldValue::F64(value)));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.push((field.clone(), FieldValue::Bool(value)));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0
+            .push((field.clone(), FieldValue::Str(value.to_string())));
+    }
+
+    fn record_error(&mut self, field: &Field, value: &(dyn std::error::Error + 'static)) {
+        let formatted = tracing::field::display(value.to_string());
+        self.0
+            .push((field.clone(), FieldValue::Formatted(formatted)));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        let formatted = tracing::field::display(format!("{:?}", value));
+        self.0
+            .push((field.clone(), FieldValue::Formatted(formatted)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        future::Future,
+        pin::Pin,
+        sync::{Arc, Mutex},
+    };
+
+    use opentelemetry::{
+        global,
+        sdk::{
+            export::trace::{ExportResult, SpanData, SpanExporter},
+            propagation::TraceContextPropagator,
+            trace::TracerProvider,
+        },
+        trace::TracerProvider as _,
+    };
+    use tracing_subscriber::fmt::MakeWriter;
+
+    use super::mask;
+    use crate::{
+        configuration::{LogFormat, RedactionPolicy, TelemetrySettings},
+        telemetry::{get_subscriber, trace_context_headers},
+    };
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'a> MakeWriter<'a> for Buffer {
+        type Writer = Self;
+
+        fn make_writer(&'a self) -> Self::Writer {
+            self.clone()
+        }
+    }
+
+    fn log_signup(format: LogFormat, policy: RedactionPolicy) -> String {
+        let mut settings = TelemetrySettings {
+            format,
+            ..TelemetrySettings::default()
+        };
+        settings.redaction.policy = policy;
+        let buffer = Buffer::default();
+        let subscriber = get_subscriber(
+            "test".into(),
+            "info".into(),
+            buffer.clone(),
+            &settings,
+            None,
+        );
+
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!(
+                "Adding a new subscriber",
+                http.target = "/subscriptions/confirm?subscription_token=secret-token&x=1",
+                subscriber_email = %"ursula_le_guin@gmail.com",
+                subscriber_id = tracing::field::Empty,
+            );
+            let _guard = span.enter();
+            span.record("subscriber_id", 42);
+            tracing::info_span!("Inserting", subscriber_name = "ursula").in_scope(|| {
+                tracing::info!(subscription_token = "secret-token", "Stored the token");
+            });
+        });
+
+        let output = buffer.0.lock().unwrap().clone();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn bunyan_output_hashes_sensitive_fields() {
+        let output = log_signup(LogFormat::Bunyan, RedactionPolicy::Hash);
+
+        assert!(!output.contains("ursula_le_guin@gmail.com"), "{}", output);
+        assert!(!output.contains("\"ursula\""), "{}", output);
+        assert!(
+            output.contains("\"subscriber_email\":\"sha256:"),
+            "{}",
+            output
+        );
+        assert!(output.contains("\"subscriber_id\":42"), "{}", output);
+        assert!(!output.contains("secret-token"), "{}", output);
+        assert!(output.contains("?subscription_token=sha256:"), "{}", output);
+        assert!(
+            output.contains("\"subscription_token\":\"sha256:"),
+            "{}",
+            output
+        );
+    }
+
+    #[test]
+    fn bunyan_output_can_drop_sensitive_fields() {
+        let output = log_signup(LogFormat::Bunyan, RedactionPolicy::Drop);
+
+        assert!(!output.contains("subscriber_email"), "{}", output);
+        assert!(!output.contains("subscriber_name"), "{}", output);
+        assert!(!output.contains("secret-token"), "{}", output);
+        assert!(output.contains("\"subscriber_id\":42"), "{}", output);
+        assert!(output.contains("/subscriptions/confirm?x=1"), "{}", output);
+    }
+
+    #[test]
+    fn pretty_output_masks_span_and_event_fields() {
+        let output = log_signup(LogFormat::Pretty, RedactionPolicy::Mask);
+
+        assert!(!output.contains("ursula_le_guin@gmail.com"), "{}", output);
+        assert!(!output.contains("secret-token"), "{}", output);
+        assert!(output.contains("u***@gmail.com"), "{}", output);
+    }
+
+    #[derive(Debug, Clone, Default)]
+    struct Exported(Arc<Mutex<Vec<SpanData>>>);
+
+    impl SpanExporter for Exported {
+        fn export(
+            &mut self,
+            batch: Vec<SpanData>,
+        ) -> Pin<Box<dyn Future<Output = ExportResult> + Send + 'static>> {
+            self.0.lock().unwrap().extend(batch);
+            Box::pin(std::future::ready(Ok(())))
+        }
+    }
+
+    #[test]
+    fn exported_spans_and_events_are_redacted() {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        let exported = Exported::default();
+        let provider = TracerProvider::builder()
+            .with_simple_exporter(exported.clone())
+            .build();
+        let subscriber = get_subscriber(
+            "test".into(),
+            "info".into(),
+            std::io::sink,
+            &TelemetrySettings::default(),
+            Some(provider.tracer("test")),
+        );
+
+        let headers = tracing::subscriber::with_default(subscriber, || {
+            tracing::info_span!(
+                "Adding a new subscriber",
+                subscriber_email = %"ursula_le_guin@gmail.com",
+            )
+            .in_scope(|| {
+                tracing::info!(subscription_token = "secret-token", "Stored the token");
+                trace_context_headers()
+            })
+        });
+        // The OpenTelemetry layer is still reachable behind the redaction.
+        assert!(headers.contains_key("traceparent"), "{:?}", headers);
+        // Shutting the provider down waits for the spans to be exported.
+        drop(provider);
+
+        let spans = format!("{:?}", exported.0.lock().unwrap());
+        assert!(spans.contains("subscriber_email"), "{}", spans);
+        assert!(!spans.contains("ursula_le_guin@gmail.com"), "{}", spans);
+        assert!(spans.contains("subscription_token"), "{}", spans);
+        assert!(!spans.contains("secret-token"), "{}", spans);
+    }
+
+    #[test]
+    fn masking_keeps_the_first_character_and_the_domain() {
+        assert_eq!(mask("ursula@gmail.com"), "u***@gmail.com");
+        assert_eq!(mask("ursula"), "u***");
+        assert_eq!(mask(""), "***");
+    }
+}
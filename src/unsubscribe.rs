@@ -0,0 +1,122 @@
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+use uuid::Uuid;
+
+#[derive(thiserror::Error, Debug)]
+pub enum UnsubscribeTokenError {
+    #[error("the unsubscribe token is malformed")]
+    Malformed,
+    #[error("the unsubscribe token signature does not match")]
+    InvalidSignature,
+}
+
+/// Builds and checks per-subscriber unsubscribe links. The token is the
+/// subscriber id followed by its HMAC, so it can be verified without touching
+/// the database.
+#[derive(Clone)]
+pub struct UnsubscribeLinks {
+    base_url: String,
+    hmac_secret: Secret<String>,
+}
+
+impl UnsubscribeLinks {
+    pub fn new(base_url: String, hmac_secret: Secret<String>) -> Self {
+        Self {
+            base_url,
+            hmac_secret,
+        }
+    }
+
+    pub fn link(&self, subscriber_id: Uuid) -> String {
+        format!(
+            "{}/subscriptions/unsubscribe?token={}",
+            self.base_url,
+            self.token(subscriber_id)
+        )
+    }
+
+    pub fn token(&self, subscriber_id: Uuid) -> String {
+        let signature = self.mac(subscriber_id).finalize().into_bytes();
+        format!(
+            "{}.{}",
+            subscriber_id.simple(),
+            base64::encode_config(signature, base64::URL_SAFE_NO_PAD)
+        )
+    }
+
+    /// Returns the subscriber the token was issued for.
+    pub fn verify(&self, token: &str) -> Result<Uuid, UnsubscribeTokenError> {
+        let (subscriber_id, signature) = token
+            .split_once('.')
+            .ok_or(UnsubscribeTokenError::Malformed)?;
+        let subscriber_id =
+            Uuid::parse_str(subscriber_id).map_err(|_| UnsubscribeTokenError::Malformed)?;
+        let signature = base64::decode_config(signature, base64::URL_SAFE_NO_PAD)
+            .map_err(|_| UnsubscribeTokenError::Malformed)?;
+
+        self.mac(subscriber_id)
+            .verify_slice(&signature)
+            .map_err(|_| UnsubscribeTokenError::InvalidSignature)?;
+
+        Ok(subscriber_id)
+    }
+
+    fn mac(&self, subscriber_id: Uuid) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.hmac_secret.expose_secret().as_bytes())
+            .expect("HMAC accepts keys of any length");
+        mac.update(b"unsubscribe:");
+        mac.update(subscriber_id.as_bytes());
+        mac
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claim::{assert_err, assert_ok_eq};
+    use secrecy::Secret;
+    use uuid::Uuid;
+
+    use super::UnsubscribeLinks;
+
+    const SECRET: &str = "super-long-and-secret-random-key-needed-to-verify-message-integrity";
+    const OTHER_SECRET: &str =
+        "another-long-and-secret-random-key-needed-to-verify-message-integrity";
+
+    fn links(secret: &str) -> UnsubscribeLinks {
+        UnsubscribeLinks::new(
+            "http://localhost".to_string(),
+            Secret::new(secret.to_string()),
+        )
+    }
+
+    #[test]
+    fn token_round_trips() {
+        let links = links(SECRET);
+        let subscriber_id = Uuid::new_v4();
+
+        assert_ok_eq!(links.verify(&links.token(subscriber_id)), subscriber_id);
+    }
+
+    #[test]
+    fn token_signed_with_another_secret_is_rejected() {
+        let token = links(SECRET).token(Uuid::new_v4());
+
+        assert_err!(links(OTHER_SECRET).verify(&token));
+    }
+
+    #[test]
+    fn token_for_another_subscriber_is_rejected() {
+        let links = links(SECRET);
+        let token = links.token(Uuid::new_v4());
+        let (_, signature) = token.split_once('.').unwrap();
+        let forged = format!("{}.{}", Uuid::new_v4().simple(), signature);
+
+        assert_err!(links.verify(&forged));
+    }
+
+    #[test]
+    fn malformed_token_is_rejected() {
+        assert_err!(links(SECRET).verify("not-a-token"));
+    }
+}
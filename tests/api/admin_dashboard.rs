@@ -0,0 +1,61 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app};
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_access_the_admin_dashboard() {
+    let app = spawn_app().await;
+
+    let res = app.get_admin_dashboard().await;
+
+    assert_is_redirect_to(&res, "/login");
+}
+
+#[tokio::test]
+async fn basic_auth_grants_access_to_the_admin_dashboard() {
+    let app = spawn_app().await;
+
+    let res = reqwest::Client::new()
+        .get(format!("http://{}/admin/dashboard", app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(res.status().as_u16(), 200);
+    assert!(res
+        .text()
+        .await
+        .unwrap()
+        .contains(&format!("Welcome {}", app.test_user.username)));
+}
+
+#[tokio::test]
+async fn invalid_basic_auth_is_rejected_on_the_admin_dashboard() {
+    let app = spawn_app().await;
+
+    let res = reqwest::Client::new()
+        .get(format!("http://{}/admin/dashboard", app.address))
+        .basic_auth(&app.test_user.username, Some("wrong-password"))
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(res.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn logout_clears_session_state() {
+    let app = spawn_app().await;
+
+    app.login_test_user().await;
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains(&format!("Welcome {}", app.test_user.username)));
+
+    let res = app.post_logout().await;
+    assert_is_redirect_to(&res, "/login");
+
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("<p><i>You have successfully logged out.</i></p>"));
+
+    let res = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&res, "/login");
+}
@@ -0,0 +1,83 @@
+use zero2prod::telemetry::{current_log_filter, parse_log_filter, set_log_filter};
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+
+/// Puts the log filter, which every test in this binary shares, back the way
+/// it was when dropped, even if the test fails.
+struct RestoreLogFilter(String);
+
+impl RestoreLogFilter {
+    fn new() -> Self {
+        Self(current_log_filter().expect("Failed to read the log filter"))
+    }
+}
+
+impl Drop for RestoreLogFilter {
+    fn drop(&mut self) {
+        let filter = parse_log_filter(&self.0).expect("Failed to parse the log filter");
+        set_log_filter(filter).expect("Failed to restore the log filter");
+    }
+}
+
+fn log_level_url(app: &TestApp) -> String {
+    format!("http://{}/admin/log-level", app.address)
+}
+
+async fn put_log_level(app: &TestApp, filter: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .put(log_level_url(app))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .json(&serde_json::json!({ "filter": filter }))
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_change_the_log_level() {
+    let app = spawn_app().await;
+
+    let res = app
+        .api_client
+        .put(log_level_url(&app))
+        .json(&serde_json::json!({ "filter": "debug" }))
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_is_redirect_to(&res, "/login");
+}
+
+#[tokio::test]
+async fn the_log_level_can_be_read_and_changed_at_runtime() {
+    let app = spawn_app().await;
+    let _restore = RestoreLogFilter::new();
+
+    let res = put_log_level(&app, "info,zero2prod::email_client=debug").await;
+    assert_eq!(res.status().as_u16(), 200);
+
+    let res = reqwest::Client::new()
+        .get(log_level_url(&app))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(res.status().as_u16(), 200);
+    let body: serde_json::Value = res.json().await.unwrap();
+    assert!(body["filter"]
+        .as_str()
+        .unwrap()
+        .contains("zero2prod::email_client=debug"));
+}
+
+#[tokio::test]
+async fn invalid_log_filters_are_rejected() {
+    let app = spawn_app().await;
+
+    let res = put_log_level(&app, "zero2prod=loud").await;
+
+    assert_eq!(res.status().as_u16(), 400);
+    assert_eq!(res.headers()["Content-Type"], "application/problem+json");
+    let body: serde_json::Value = res.json().await.unwrap();
+    assert_eq!(body["errors"][0]["field"], "filter");
+}
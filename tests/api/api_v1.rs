@@ -0,0 +1,79 @@
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::spawn_app;
+
+#[tokio::test]
+async fn openapi_document_describes_the_v1_routes() {
+    let app = spawn_app().await;
+
+    let res = reqwest::get(format!("http://{}/api/v1/openapi.json", app.address))
+        .await
+        .unwrap();
+
+    assert_eq!(res.status().as_u16(), 200);
+    let doc: serde_json::Value = res.json().await.unwrap();
+    assert!(doc["openapi"].as_str().unwrap().starts_with("3."));
+    for route in [
+        "/api/v1/subscriptions",
+        "/api/v1/subscriptions/confirm",
+        "/api/v1/subscriptions/unsubscribe",
+    ] {
+        assert!(
+            doc["paths"].get(route).is_some(),
+            "{} is undocumented",
+            route
+        );
+    }
+
+    let subscribe_body = &doc["paths"]["/api/v1/subscriptions"]["post"]["requestBody"]["content"];
+    assert!(subscribe_body.get("application/json").is_some());
+    assert!(subscribe_body
+        .get("application/x-www-form-urlencoded")
+        .is_some());
+
+    let confirm = &doc["paths"]["/api/v1/subscriptions/confirm"]["get"];
+    assert_eq!(confirm["parameters"][0]["name"], "subscription_token");
+    assert!(confirm["responses"].get("410").is_some());
+
+    let schemas = &doc["components"]["schemas"];
+    for schema in ["FormData", "ProblemDetails", "FieldError"] {
+        assert!(schemas.get(schema).is_some(), "{} is missing", schema);
+    }
+    assert!(schemas["ProblemDetails"]["properties"]
+        .get("type")
+        .is_some());
+}
+
+#[tokio::test]
+async fn subscriptions_are_served_under_api_v1() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let res = reqwest::Client::new()
+        .post(format!("http://{}/api/v1/subscriptions", app.address))
+        .json(&serde_json::json!({
+            "name": "le guin",
+            "email": "ursula_le_guin@gmail.com",
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status().as_u16(), 200);
+
+    let res = reqwest::get(format!(
+        "http://{}/api/v1/subscriptions/confirm?subscription_token=unknown",
+        app.address
+    ))
+    .await
+    .unwrap();
+    assert_eq!(res.status().as_u16(), 404);
+}
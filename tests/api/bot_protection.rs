@@ -0,0 +1,208 @@
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+use zero2prod::{bot_protection::work_done, configuration::Settings};
+
+use crate::helpers::{spawn_app, spawn_app_with, TestApp};
+
+const EMAIL: &str = "ursula_le_guin@gmail.com";
+
+async fn expect_emails(app: &TestApp, count: u64) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(count)
+        .mount(&app.email_server)
+        .await;
+}
+
+async fn get_challenge(app: &TestApp) -> serde_json::Value {
+    let res = reqwest::get(format!("http://{}/subscriptions/challenge", app.address))
+        .await
+        .unwrap();
+    assert_eq!(res.status().as_u16(), 200);
+    assert_eq!(res.headers()["Cache-Control"], "no-store");
+    res.json().await.unwrap()
+}
+
+async fn subscriber_count(app: &TestApp) -> i64 {
+    sqlx::query!(r#"SELECT count(*) AS "count!" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count
+}
+
+fn without_min_submit_time(c: &mut Settings) {
+    c.bot_protection.min_submit_seconds = 0;
+}
+
+#[tokio::test]
+async fn a_filled_in_honeypot_looks_successful_but_sends_no_email() {
+    let app = spawn_app().await;
+    expect_emails(&app, 0).await;
+
+    let res = app
+        .post_subscriptions(
+            "name=le%20guin&email=ursula_le_guin%40gmail.com&website=http%3A%2F%2Fspam.example.com"
+                .to_string(),
+        )
+        .await;
+
+    assert_eq!(res.status().as_u16(), 200);
+    assert_eq!(subscriber_count(&app).await, 0);
+}
+
+#[tokio::test]
+async fn forms_submitted_right_after_loading_are_dropped() {
+    let app = spawn_app().await;
+    expect_emails(&app, 0).await;
+    let challenge = get_challenge(&app).await;
+
+    let res = app
+        .post_subscriptions_json(&serde_json::json!({
+            "name": "le guin",
+            "email": EMAIL,
+            "form_token": challenge["form_token"],
+        }))
+        .await;
+
+    assert_eq!(res.status().as_u16(), 200);
+    let body: serde_json::Value = res.json().await.unwrap();
+    assert!(body["message"].is_string());
+    assert_eq!(subscriber_count(&app).await, 0);
+}
+
+#[tokio::test]
+async fn forms_with_a_valid_token_are_accepted() {
+    let app = spawn_app_with(without_min_submit_time).await;
+    expect_emails(&app, 1).await;
+    let challenge = get_challenge(&app).await;
+
+    let res = app
+        .post_subscriptions_json(&serde_json::json!({
+            "name": "le guin",
+            "email": EMAIL,
+            "form_token": challenge["form_token"],
+        }))
+        .await;
+
+    assert_eq!(res.status().as_u16(), 200);
+    assert_eq!(subscriber_count(&app).await, 1);
+}
+
+#[tokio::test]
+async fn form_tokens_are_only_good_for_one_signup() {
+    let app = spawn_app_with(without_min_submit_time).await;
+    expect_emails(&app, 1).await;
+    let challenge = get_challenge(&app).await;
+
+    for email in [EMAIL, "someone_else@gmail.com"] {
+        let res = app
+            .post_subscriptions_json(&serde_json::json!({
+                "name": "le guin",
+                "email": email,
+                "form_token": challenge["form_token"],
+            }))
+            .await;
+        assert_eq!(res.status().as_u16(), 200);
+    }
+
+    assert_eq!(subscriber_count(&app).await, 1);
+}
+
+#[tokio::test]
+async fn a_form_token_is_not_used_up_by_an_invalid_signup() {
+    let app = spawn_app_with(without_min_submit_time).await;
+    expect_emails(&app, 1).await;
+    let challenge = get_challenge(&app).await;
+
+    let res = app
+        .post_subscriptions_json(&serde_json::json!({
+            "name": "le guin",
+            "email": "not-an-email",
+            "form_token": challenge["form_token"],
+        }))
+        .await;
+    assert_eq!(res.status().as_u16(), 400);
+
+    let res = app
+        .post_subscriptions_json(&serde_json::json!({
+            "name": "le guin",
+            "email": EMAIL,
+            "form_token": challenge["form_token"],
+        }))
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+    assert_eq!(subscriber_count(&app).await, 1);
+}
+
+#[tokio::test]
+async fn forged_form_tokens_are_dropped() {
+    let app = spawn_app_with(without_min_submit_time).await;
+    expect_emails(&app, 0).await;
+
+    let res = app
+        .post_subscriptions_json(&serde_json::json!({
+            "name": "le guin",
+            "email": EMAIL,
+            "form_token": "0.bm9uY2U.c2lnbmF0dXJl",
+        }))
+        .await;
+
+    assert_eq!(res.status().as_u16(), 200);
+    assert_eq!(subscriber_count(&app).await, 0);
+}
+
+#[tokio::test]
+async fn signups_without_a_form_token_are_dropped_when_one_is_required() {
+    let app = spawn_app_with(|c: &mut Settings| c.bot_protection.require_form_token = true).await;
+    expect_emails(&app, 0).await;
+
+    let res = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".to_string())
+        .await;
+
+    assert_eq!(res.status().as_u16(), 200);
+    assert_eq!(subscriber_count(&app).await, 0);
+}
+
+#[tokio::test]
+async fn signups_need_a_proof_of_work_when_one_is_configured() {
+    let app = spawn_app_with(|c: &mut Settings| {
+        without_min_submit_time(c);
+        c.bot_protection.proof_of_work_difficulty = 8;
+    })
+    .await;
+    expect_emails(&app, 1).await;
+
+    let challenge = get_challenge(&app).await;
+    assert_eq!(challenge["proof_of_work_difficulty"], 8);
+    let form_token = challenge["form_token"].as_str().unwrap();
+
+    let unsolved = app
+        .post_subscriptions_json(&serde_json::json!({
+            "name": "le guin",
+            "email": EMAIL,
+            "form_token": form_token,
+        }))
+        .await;
+    assert_eq!(unsolved.status().as_u16(), 200);
+    assert_eq!(subscriber_count(&app).await, 0);
+
+    let solution = (0u64..)
+        .map(|n| n.to_string())
+        .find(|solution| work_done(form_token, EMAIL, solution) >= 8)
+        .unwrap();
+    let solved = app
+        .post_subscriptions_json(&serde_json::json!({
+            "name": "le guin",
+            "email": EMAIL,
+            "form_token": form_token,
+            "proof_of_work": solution,
+        }))
+        .await;
+    assert_eq!(solved.status().as_u16(), 200);
+    assert_eq!(subscriber_count(&app).await, 1);
+}
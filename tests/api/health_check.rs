@@ -1,4 +1,6 @@
-use crate::helpers::spawn_app;
+use wiremock::{matchers::any, Mock, ResponseTemplate};
+
+use crate::helpers::{spawn_app, spawn_app_with};
 
 #[tokio::test]
 async fn health_check_works() {
@@ -13,3 +15,77 @@ async fn health_check_works() {
     assert!(res.status().is_success());
     assert_eq!(Some(0), res.content_length());
 }
+
+#[tokio::test]
+async fn liveness_reports_up() {
+    let app = spawn_app().await;
+
+    let res = reqwest::get(format!("http://{}/health/live", app.address))
+        .await
+        .unwrap();
+
+    assert_eq!(res.status().as_u16(), 200);
+    let body: serde_json::Value = res.json().await.unwrap();
+    assert_eq!(body["status"], "up");
+}
+
+#[tokio::test]
+async fn readiness_reports_database_status_and_pool_statistics() {
+    let app = spawn_app().await;
+
+    let res = reqwest::get(format!("http://{}/health/ready", app.address))
+        .await
+        .unwrap();
+
+    assert_eq!(res.status().as_u16(), 200);
+    let body: serde_json::Value = res.json().await.unwrap();
+    assert_eq!(body["status"], "up");
+    assert_eq!(body["components"]["database"]["status"], "up");
+    assert!(body["components"]["database"]["pool"]["size"].is_u64());
+    assert!(body["components"]["email"].is_null());
+}
+
+#[tokio::test]
+async fn readiness_probes_the_email_provider_when_enabled() {
+    let app = spawn_app_with(|c| c.health.probe_email = true).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(404))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let res = reqwest::get(format!("http://{}/health/ready", app.address))
+        .await
+        .unwrap();
+
+    assert_eq!(res.status().as_u16(), 200);
+    let body: serde_json::Value = res.json().await.unwrap();
+    assert_eq!(body["components"]["email"]["status"], "up");
+}
+
+#[tokio::test]
+async fn readiness_fails_with_a_503_when_the_email_provider_is_unreachable() {
+    let app = spawn_app_with(|c| {
+        c.health.probe_email = true;
+        c.health.email_timeout_milliseconds = 100;
+    })
+    .await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200).set_delay(std::time::Duration::from_secs(5)))
+        .mount(&app.email_server)
+        .await;
+
+    let res = reqwest::get(format!("http://{}/health/ready", app.address))
+        .await
+        .unwrap();
+
+    assert_eq!(res.status().as_u16(), 503);
+    let body: serde_json::Value = res.json().await.unwrap();
+    assert_eq!(body["status"], "down");
+    assert_eq!(body["components"]["database"]["status"], "up");
+    assert_eq!(body["components"]["email"]["status"], "down");
+    // Why it is down is only logged.
+    assert!(body["components"]["email"]["error"].is_null());
+}
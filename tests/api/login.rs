@@ -0,0 +1,65 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app};
+
+#[tokio::test]
+async fn login_redirects_to_admin_dashboard_on_valid_credentials() {
+    let app = spawn_app().await;
+    let body = serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password,
+    });
+
+    let res = app.post_login(&body).await;
+    assert_is_redirect_to(&res, "/admin/dashboard");
+
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains(&format!("Welcome {}", app.test_user.username)));
+}
+
+#[tokio::test]
+async fn an_error_flash_message_is_set_on_failure() {
+    let app = spawn_app().await;
+    let test_cases = vec![
+        (
+            serde_json::json!({
+                "username": &app.test_user.username,
+                "password": "wrong-password",
+            }),
+            "wrong password",
+        ),
+        (
+            serde_json::json!({
+                "username": "unknown-user",
+                "password": &app.test_user.password,
+            }),
+            "unknown username",
+        ),
+    ];
+
+    for (body, error_message) in test_cases {
+        let res = app.post_login(&body).await;
+        assert_is_redirect_to(&res, "/login");
+
+        let html_page = app.get_login_html().await;
+        assert!(
+            html_page.contains("<p><i>Authentication failed.</i></p>"),
+            "The login page did not show an error when the login had a {}.",
+            error_message
+        );
+
+        let html_page = app.get_login_html().await;
+        assert!(!html_page.contains("Authentication failed."));
+    }
+}
+
+#[tokio::test]
+async fn the_configured_administrator_can_log_in_to_a_fresh_deployment() {
+    let app = spawn_app().await;
+    let body = serde_json::json!({
+        "username": "admin",
+        "password": "everythinghastostartsomewhere",
+    });
+
+    let res = app.post_login(&body).await;
+
+    assert_is_redirect_to(&res, "/admin/dashboard");
+}
@@ -1,4 +1,13 @@
+mod admin_dashboard;
+mod admin_log_level;
+mod api_v1;
+mod bot_protection;
 mod health_check;
 mod helpers;
+mod login;
+mod metrics;
+mod newsletters;
+mod rate_limit;
 mod subscriptions;
 mod subscriptions_confirm;
+mod subscriptions_unsubscribe;
@@ -0,0 +1,84 @@
+use crate::helpers::{create_confirmed_subscriber, spawn_app, spawn_app_with, TestApp};
+
+async fn get_metrics_with_token(app: &TestApp, token: Option<&str>) -> reqwest::Response {
+    let mut request = reqwest::Client::new().get(format!("http://{}/metrics", app.address));
+    if let Some(token) = token {
+        request = request.bearer_auth(token);
+    }
+    request.send().await.expect("Failed to execute request")
+}
+
+async fn get_metrics(app: &TestApp) -> String {
+    let res = get_metrics_with_token(app, app.metrics_bearer_token.as_deref()).await;
+    assert_eq!(res.status().as_u16(), 200);
+    assert!(res.headers()["Content-Type"]
+        .to_str()
+        .unwrap()
+        .starts_with("text/plain"));
+    res.text().await.unwrap()
+}
+
+#[tokio::test]
+async fn metrics_report_requests_by_route_pattern() {
+    let app = spawn_app().await;
+    app.get_subscription_confirm("not-a-real-token").await;
+    app.get_subscription_confirm("another-token").await;
+
+    let metrics = get_metrics(&app).await;
+
+    assert!(metrics.contains(
+        r#"zero2prod_http_requests_total{method="GET",route="/subscriptions/confirm",status="404"}"#
+    ));
+    assert!(metrics.contains(
+        r#"zero2prod_http_request_duration_seconds_bucket{method="GET",route="/subscriptions/confirm""#
+    ));
+    assert!(!metrics.contains("not-a-real-token"));
+}
+
+#[tokio::test]
+async fn metrics_report_pool_email_and_subscription_activity() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    let metrics = get_metrics(&app).await;
+
+    for name in [
+        "zero2prod_db_pool_connections",
+        "zero2prod_db_pool_idle_connections",
+        r#"zero2prod_email_send_duration_seconds_count{outcome="success"}"#,
+        r#"zero2prod_email_api_responses_total{status="200"}"#,
+        "zero2prod_signups_total",
+        "zero2prod_confirmations_total",
+    ] {
+        assert!(
+            metrics.contains(name),
+            "{} is missing from\n{}",
+            name,
+            metrics
+        );
+    }
+}
+
+#[tokio::test]
+async fn metrics_require_the_bearer_token() {
+    let app = spawn_app().await;
+
+    for token in [None, Some("wrong-token")] {
+        let res = get_metrics_with_token(&app, token).await;
+
+        assert_eq!(res.status().as_u16(), 401);
+        assert_eq!(
+            res.headers()["WWW-Authenticate"],
+            r#"Bearer realm="metrics""#
+        );
+    }
+}
+
+#[tokio::test]
+async fn metrics_are_not_served_without_a_configured_token() {
+    let app = spawn_app_with(|c| c.metrics.bearer_token = None).await;
+
+    let res = get_metrics_with_token(&app, Some("local-metrics-token")).await;
+
+    assert_eq!(res.status().as_u16(), 404);
+}
@@ -0,0 +1,318 @@
+use uuid::Uuid;
+use wiremock::{
+    matchers::{any, method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{
+    create_confirmed_subscriber, create_unconfirmed_subscriber, spawn_app, TestApp,
+};
+
+fn newsletter_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    })
+}
+
+#[tokio::test]
+async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
+    let app = spawn_app().await;
+    create_unconfirmed_subscriber(&app).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let res = app.post_newsletters(newsletter_body()).await;
+
+    assert_eq!(res.status().as_u16(), 200);
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn newsletters_are_delivered_to_confirmed_subscribers() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let res = app.post_newsletters(newsletter_body()).await;
+
+    assert_eq!(res.status().as_u16(), 200);
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn newsletters_skip_subscribers_with_invalid_stored_emails() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+        VALUES(gen_random_uuid(), 'not an email', 'broken', now(), 'confirmed')
+        "#,
+    )
+    .execute(&app.db_pool)
+    .await
+    .expect("Failed to insert subscriber");
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let res = app.post_newsletters(newsletter_body()).await;
+
+    assert_eq!(res.status().as_u16(), 200);
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn newsletters_returns_400_for_invalid_data() {
+    let app = spawn_app().await;
+    let test_cases = vec![
+        (
+            serde_json::json!({
+                "content": {
+                    "text": "Newsletter body as plain text",
+                    "html": "<p>Newsletter body as HTML</p>",
+                }
+            }),
+            "missing title",
+        ),
+        (
+            serde_json::json!({"title": "Newsletter!"}),
+            "missing content",
+        ),
+    ];
+
+    for (invalid_body, error_message) in test_cases {
+        let res = app.post_newsletters(invalid_body).await;
+
+        assert_eq!(
+            400,
+            res.status().as_u16(),
+            "The API did not fail with 400 Bad Request when the payload was {}.",
+            error_message
+        );
+    }
+}
+
+#[tokio::test]
+async fn delivered_tasks_are_removed_from_the_queue() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_newsletters(newsletter_body())
+        .await
+        .error_for_status()
+        .unwrap();
+    assert_eq!(pending_tasks(&app).await, 1);
+
+    app.dispatch_all_pending_emails().await;
+    assert_eq!(pending_tasks(&app).await, 0);
+}
+
+#[tokio::test]
+async fn failed_deliveries_are_rescheduled() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1..)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_newsletters(newsletter_body())
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    let task = sqlx::query!("SELECT n_retries, execute_after FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch the delivery task");
+    assert_eq!(task.n_retries, 1);
+    assert!(task.execute_after > chrono::Utc::now());
+}
+
+async fn pending_tasks(app: &TestApp) -> i64 {
+    sqlx::query!(r#"SELECT count(*) as "count!" FROM issue_delivery_queue"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to count delivery tasks")
+        .count
+}
+
+#[tokio::test]
+async fn requests_missing_authorization_are_rejected() {
+    let app = spawn_app().await;
+
+    let res = reqwest::Client::new()
+        .post(format!("http://{}/newsletters", &app.address))
+        .json(&newsletter_body())
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(401, res.status().as_u16());
+    assert_eq!(
+        r#"Basic realm="publish""#,
+        res.headers()["WWW-Authenticate"]
+    );
+}
+
+#[tokio::test]
+async fn non_existing_user_is_rejected() {
+    let app = spawn_app().await;
+    let username = Uuid::new_v4().to_string();
+    let password = Uuid::new_v4().to_string();
+
+    let res = reqwest::Client::new()
+        .post(format!("http://{}/newsletters", &app.address))
+        .basic_auth(username, Some(password))
+        .json(&newsletter_body())
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(401, res.status().as_u16());
+    assert_eq!(
+        r#"Basic realm="publish""#,
+        res.headers()["WWW-Authenticate"]
+    );
+}
+
+#[tokio::test]
+async fn invalid_password_is_rejected() {
+    let app = spawn_app().await;
+    let username = &app.test_user.username;
+    let password = Uuid::new_v4().to_string();
+    assert_ne!(app.test_user.password, password);
+
+    let res = reqwest::Client::new()
+        .post(format!("http://{}/newsletters", &app.address))
+        .basic_auth(username, Some(password))
+        .json(&newsletter_body())
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(401, res.status().as_u16());
+    assert_eq!(
+        r#"Basic realm="publish""#,
+        res.headers()["WWW-Authenticate"]
+    );
+}
+
+#[tokio::test]
+async fn newsletter_creation_is_idempotent() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let idempotency_key = Uuid::new_v4().to_string();
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let res = app
+        .post_newsletters_with_idempotency_key(newsletter_body(), &idempotency_key)
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+
+    let res = app
+        .post_newsletters_with_idempotency_key(newsletter_body(), &idempotency_key)
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn reusing_an_idempotency_key_for_another_issue_is_rejected() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let idempotency_key = Uuid::new_v4().to_string();
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let res = app
+        .post_newsletters_with_idempotency_key(newsletter_body(), &idempotency_key)
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+
+    let mut other_issue = newsletter_body();
+    other_issue["title"] = "Another issue".into();
+    let res = app
+        .post_newsletters_with_idempotency_key(other_issue, &idempotency_key)
+        .await;
+    assert_eq!(res.status().as_u16(), 422);
+
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn concurrent_newsletter_submissions_are_handled_gracefully() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let idempotency_key = Uuid::new_v4().to_string();
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let res1 = app.post_newsletters_with_idempotency_key(newsletter_body(), &idempotency_key);
+    let res2 = app.post_newsletters_with_idempotency_key(newsletter_body(), &idempotency_key);
+    let (res1, res2) = tokio::join!(res1, res2);
+
+    assert_eq!(res1.status(), res2.status());
+    assert_eq!(res1.text().await.unwrap(), res2.text().await.unwrap());
+
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn an_invalid_idempotency_key_is_rejected() {
+    let app = spawn_app().await;
+
+    let res = app
+        .post_newsletters_with_idempotency_key(newsletter_body(), &"a".repeat(50))
+        .await;
+
+    assert_eq!(res.status().as_u16(), 400);
+}
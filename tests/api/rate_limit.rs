@@ -0,0 +1,167 @@
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+use zero2prod::configuration::{RateLimitStoreKind, Settings};
+
+use crate::helpers::{spawn_app_with, TestApp};
+
+fn with_per_ip_capacity(capacity: u32) -> impl FnOnce(&mut Settings) {
+    move |c: &mut Settings| {
+        c.rate_limit.per_ip.capacity = capacity;
+        c.rate_limit.per_ip.refill_interval_seconds = 3600.0;
+    }
+}
+
+async fn confirm_from(app: &TestApp, forwarded_for: Option<&str>) -> reqwest::Response {
+    let mut request = reqwest::Client::new().get(format!(
+        "http://{}/subscriptions/confirm?subscription_token=unknown",
+        app.address
+    ));
+    if let Some(forwarded_for) = forwarded_for {
+        request = request.header("X-Forwarded-For", forwarded_for);
+    }
+    request.send().await.unwrap()
+}
+
+#[tokio::test]
+async fn clients_over_their_quota_get_a_429_with_retry_after() {
+    let app = spawn_app_with(with_per_ip_capacity(2)).await;
+
+    for _ in 0..2 {
+        assert_eq!(confirm_from(&app, None).await.status().as_u16(), 404);
+    }
+    let res = confirm_from(&app, None).await;
+
+    assert_eq!(res.status().as_u16(), 429);
+    let retry_after: u64 = res.headers()["Retry-After"]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(retry_after > 0 && retry_after <= 3600);
+    assert_eq!(
+        res.headers().get("Content-Type").unwrap(),
+        "application/problem+json"
+    );
+}
+
+#[tokio::test]
+async fn the_per_ip_quota_is_shared_by_every_limited_route() {
+    let app = spawn_app_with(with_per_ip_capacity(1)).await;
+
+    assert_eq!(confirm_from(&app, None).await.status().as_u16(), 404);
+    let res = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".to_string())
+        .await;
+
+    assert_eq!(res.status().as_u16(), 429);
+}
+
+#[tokio::test]
+async fn forwarded_for_identifies_clients_behind_trusted_proxies() {
+    let app = spawn_app_with(|c: &mut Settings| {
+        with_per_ip_capacity(1)(c);
+        c.rate_limit.trusted_proxies = vec!["127.0.0.1".into()];
+    })
+    .await;
+
+    let first = confirm_from(&app, Some("203.0.113.1")).await;
+    let other_client = confirm_from(&app, Some("203.0.113.2")).await;
+    let first_again = confirm_from(&app, Some("203.0.113.1")).await;
+
+    assert_eq!(first.status().as_u16(), 404);
+    assert_eq!(other_client.status().as_u16(), 404);
+    assert_eq!(first_again.status().as_u16(), 429);
+}
+
+#[tokio::test]
+async fn forwarded_for_is_ignored_without_trusted_proxies() {
+    let app = spawn_app_with(with_per_ip_capacity(1)).await;
+
+    confirm_from(&app, Some("203.0.113.1")).await;
+    let res = confirm_from(&app, Some("203.0.113.2")).await;
+
+    assert_eq!(res.status().as_u16(), 429);
+}
+
+#[tokio::test]
+async fn repeated_signups_for_the_same_email_are_limited() {
+    let app = spawn_app_with(|c: &mut Settings| {
+        c.rate_limit.per_email.capacity = 1;
+        c.rate_limit.per_email.refill_interval_seconds = 3600.0;
+    })
+    .await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    let first = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".to_string())
+        .await;
+    let repeat = app
+        .post_subscriptions("name=le%20guin&email=Ursula_Le_Guin%40gmail.com".to_string())
+        .await;
+    let other = app
+        .post_subscriptions("name=someone&email=someone%40gmail.com".to_string())
+        .await;
+
+    assert_eq!(first.status().as_u16(), 200);
+    assert_eq!(repeat.status().as_u16(), 429);
+    assert!(repeat.headers().contains_key("Retry-After"));
+    assert_eq!(other.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn retried_signups_replay_their_response_without_using_the_email_quota() {
+    let app = spawn_app_with(|c: &mut Settings| {
+        c.rate_limit.per_email.capacity = 1;
+        c.rate_limit.per_email.refill_interval_seconds = 3600.0;
+    })
+    .await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    let idempotency_key = uuid::Uuid::new_v4().to_string();
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    for _ in 0..3 {
+        let res = app
+            .post_subscriptions_with_idempotency_key(body.to_string(), &idempotency_key)
+            .await;
+        assert_eq!(res.status().as_u16(), 200);
+    }
+
+    let other_key = uuid::Uuid::new_v4().to_string();
+    let res = app
+        .post_subscriptions_with_idempotency_key(body.to_string(), &other_key)
+        .await;
+    assert_eq!(res.status().as_u16(), 429);
+}
+
+#[tokio::test]
+async fn the_postgres_store_keeps_buckets_in_the_database() {
+    let app = spawn_app_with(|c: &mut Settings| {
+        with_per_ip_capacity(1)(c);
+        c.rate_limit.store = RateLimitStoreKind::Postgres;
+    })
+    .await;
+
+    assert_eq!(confirm_from(&app, None).await.status().as_u16(), 404);
+    assert_eq!(confirm_from(&app, None).await.status().as_u16(), 429);
+
+    let bucket = sqlx::query!("SELECT key, tokens FROM rate_limit_buckets")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(bucket.key, "ip:127.0.0.1");
+    assert!(bucket.tokens < 1.0);
+}
@@ -1,9 +1,14 @@
+use std::time::Duration;
+
+use uuid::Uuid;
 use wiremock::{
     matchers::{method, path},
     Mock, ResponseTemplate,
 };
 
-use crate::helpers::spawn_app;
+use zero2prod::configuration::Settings;
+
+use crate::helpers::{create_confirmed_subscriber, spawn_app, spawn_app_with, TestApp};
 
 #[tokio::test]
 async fn subscriptions_returns_200_for_valid_data() {
@@ -55,6 +60,174 @@ async fn subscriptions_returns_400_for_invalid_data() {
     }
 }
 
+#[tokio::test]
+async fn subscriptions_report_every_invalid_field_as_problem_details() {
+    let app = spawn_app().await;
+
+    let res = app
+        .post_subscriptions("name=&email=not_an_email".to_string())
+        .await;
+
+    assert_eq!(res.status().as_u16(), 400);
+    assert_eq!(
+        res.headers().get("Content-Type").unwrap(),
+        "application/problem+json"
+    );
+    let body: serde_json::Value = res.json().await.unwrap();
+    assert_eq!(body["status"], 400);
+    assert_eq!(body["title"], "Bad Request");
+    let fields: Vec<&str> = body["errors"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| e["field"].as_str().unwrap())
+        .collect();
+    assert_eq!(fields, vec!["name", "email"]);
+    assert_eq!(body["errors"][0]["message"], "name must not be empty");
+}
+
+#[tokio::test]
+async fn subscribe_hides_the_cause_of_unexpected_errors() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&app.email_server)
+        .await;
+
+    let res = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".to_string())
+        .await;
+
+    assert_eq!(res.status().as_u16(), 500);
+    assert_eq!(
+        res.headers().get("Content-Type").unwrap(),
+        "application/problem+json"
+    );
+    let body: serde_json::Value = res.json().await.unwrap();
+    assert_eq!(body["status"], 500);
+    assert!(body.get("errors").is_none());
+}
+
+#[tokio::test]
+async fn subscriptions_accept_json_bodies_and_answer_in_json() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let res = app
+        .post_subscriptions_json(&serde_json::json!({
+            "name": "le guin",
+            "email": "ursula_le_guin@gmail.com",
+        }))
+        .await;
+
+    assert_eq!(res.status().as_u16(), 200);
+    let body: serde_json::Value = res.json().await.unwrap();
+    assert!(body["message"].is_string());
+
+    let saved = sqlx::query!("SELECT email, name FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription");
+    assert_eq!(saved.email, "ursula_le_guin@gmail.com");
+    assert_eq!(saved.name, "le guin");
+}
+
+#[tokio::test]
+async fn json_subscriptions_are_validated_like_forms() {
+    let app = spawn_app().await;
+
+    let res = app
+        .post_subscriptions_json(&serde_json::json!({"name": "le guin"}))
+        .await;
+
+    assert_eq!(res.status().as_u16(), 400);
+    let body: serde_json::Value = res.json().await.unwrap();
+    assert_eq!(body["errors"][0]["field"], "email");
+
+    let res = app.post_subscriptions_json(&serde_json::json!([])).await;
+    assert_eq!(res.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn subscriptions_reject_unknown_content_types_with_a_415() {
+    let app = spawn_app().await;
+
+    let res = reqwest::Client::new()
+        .post(format!("http://{}/subscriptions", app.address))
+        .header("Content-Type", "text/plain")
+        .body("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(res.status().as_u16(), 415);
+    assert_eq!(
+        res.headers().get("Content-Type").unwrap(),
+        "application/problem+json"
+    );
+}
+
+#[tokio::test]
+async fn subscriptions_explain_why_an_address_was_refused() {
+    let app = spawn_app_with(|c: &mut Settings| {
+        c.email_policy.denied_domains = vec!["blocked.example.com".into()];
+    })
+    .await;
+
+    let test_cases = vec![
+        (
+            "someone@yopmail.com",
+            "yopmail.com is a disposable email provider",
+        ),
+        (
+            "postmaster@gmail.com",
+            "postmaster@ is a role account, please use a personal address",
+        ),
+        (
+            "someone@eu.blocked.example.com",
+            "addresses at eu.blocked.example.com are blocked",
+        ),
+    ];
+
+    for (email, message) in test_cases {
+        let res = app
+            .post_subscriptions_json(&serde_json::json!({"name": "le guin", "email": email}))
+            .await;
+
+        assert_eq!(res.status().as_u16(), 400, "{} was accepted", email);
+        let body: serde_json::Value = res.json().await.unwrap();
+        assert_eq!(body["errors"][0]["field"], "email");
+        assert_eq!(body["errors"][0]["message"], message);
+    }
+}
+
+#[tokio::test]
+async fn subscriptions_can_be_restricted_to_allowed_domains() {
+    let app = spawn_app_with(|c: &mut Settings| {
+        c.email_policy.allowed_domains = vec!["example.com".into()];
+    })
+    .await;
+
+    let res = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".to_string())
+        .await;
+
+    assert_eq!(res.status().as_u16(), 400);
+    let body: serde_json::Value = res.json().await.unwrap();
+    assert_eq!(
+        body["errors"][0]["message"],
+        "addresses at gmail.com are not accepted"
+    );
+}
+
 #[tokio::test]
 async fn subscribe_sends_email_confirmation_for_valid_data() {
     let app = spawn_app().await;
@@ -69,3 +242,244 @@ async fn subscribe_sends_email_confirmation_for_valid_data() {
 
     app.post_subscriptions(body.to_string()).await;
 }
+
+#[tokio::test]
+async fn subscribe_is_idempotent() {
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    let idempotency_key = Uuid::new_v4().to_string();
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let res = app
+        .post_subscriptions_with_idempotency_key(body.to_string(), &idempotency_key)
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+
+    let res = app
+        .post_subscriptions_with_idempotency_key(body.to_string(), &idempotency_key)
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn reusing_an_idempotency_key_for_another_subscription_is_rejected() {
+    let app = spawn_app().await;
+    let idempotency_key = Uuid::new_v4().to_string();
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let res = app
+        .post_subscriptions_with_idempotency_key(
+            "name=le%20guin&email=ursula_le_guin%40gmail.com".to_string(),
+            &idempotency_key,
+        )
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+
+    let res = app
+        .post_subscriptions_with_idempotency_key(
+            "name=tolkien&email=tolkien%40gmail.com".to_string(),
+            &idempotency_key,
+        )
+        .await;
+    assert_eq!(res.status().as_u16(), 422);
+    assert_eq!(res.headers()["Content-Type"], "application/problem+json");
+}
+
+#[tokio::test]
+async fn expired_idempotency_keys_can_be_used_again() {
+    let app = spawn_app().await;
+    let idempotency_key = Uuid::new_v4().to_string();
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    let res = app
+        .post_subscriptions_with_idempotency_key(
+            "name=le%20guin&email=ursula_le_guin%40gmail.com".to_string(),
+            &idempotency_key,
+        )
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+
+    sqlx::query!("UPDATE idempotency SET expires_at = now() - interval '1 second'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let res = app
+        .post_subscriptions_with_idempotency_key(
+            "name=tolkien&email=tolkien%40gmail.com".to_string(),
+            &idempotency_key,
+        )
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT email FROM subscriptions WHERE email = 'tolkien@gmail.com'")
+        .fetch_optional(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(saved.is_some());
+}
+
+#[tokio::test]
+async fn concurrent_subscribe_requests_are_handled_gracefully() {
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    let idempotency_key = Uuid::new_v4().to_string();
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(2)))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let res1 = app.post_subscriptions_with_idempotency_key(body.to_string(), &idempotency_key);
+    let res2 = app.post_subscriptions_with_idempotency_key(body.to_string(), &idempotency_key);
+    let (res1, res2) = tokio::join!(res1, res2);
+
+    assert_eq!(res1.status(), res2.status());
+    assert_eq!(res1.text().await.unwrap(), res2.text().await.unwrap());
+}
+
+#[tokio::test]
+async fn subscribe_sends_a_confirmation_email_with_a_link() {
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.to_string()).await;
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_ne!(body["HtmlBody"], body["TextBody"]);
+
+    let confirmation_links = app.get_confirmation_links(email_request);
+    assert_eq!(confirmation_links.html, confirmation_links.plain_text);
+    assert_eq!(confirmation_links.html.path(), "/subscriptions/confirm");
+    assert!(confirmation_links
+        .html
+        .query_pairs()
+        .any(|(k, _)| k == "subscription_token"));
+}
+
+async fn subscriber_status(app: &TestApp) -> String {
+    sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription")
+        .status
+}
+
+#[tokio::test]
+async fn subscribing_twice_while_pending_resends_a_working_confirmation_email() {
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    let res = app.post_subscriptions(body.to_string()).await;
+    assert_eq!(res.status().as_u16(), 200);
+    let res = app.post_subscriptions(body.to_string()).await;
+    assert_eq!(res.status().as_u16(), 200);
+    assert_eq!(subscriber_status(&app).await, "pending_confirmation");
+
+    let email_requests = app.email_server.received_requests().await.unwrap();
+    let first_link = app.get_confirmation_links(&email_requests[0]).html;
+    let second_link = app.get_confirmation_links(&email_requests[1]).html;
+    assert_ne!(first_link, second_link);
+
+    reqwest::get(second_link)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    assert_eq!(subscriber_status(&app).await, "confirmed");
+}
+
+#[tokio::test]
+async fn subscribing_again_once_confirmed_succeeds_without_sending_an_email() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let res = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".to_string())
+        .await;
+
+    assert_eq!(res.status().as_u16(), 200);
+    assert_eq!(subscriber_status(&app).await, "confirmed");
+}
+
+#[tokio::test]
+async fn subscribing_again_after_unsubscribing_starts_a_new_double_opt_in() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    sqlx::query!("UPDATE subscriptions SET status = 'unsubscribed'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let res = app
+        .post_subscriptions("name=ursula&email=ursula_le_guin%40gmail.com".to_string())
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+
+    let saved = sqlx::query!("SELECT name, status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "pending_confirmation");
+    assert_eq!(saved.name, "ursula");
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    reqwest::get(app.get_confirmation_links(&email_request).html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    assert_eq!(subscriber_status(&app).await, "confirmed");
+}
@@ -3,10 +3,36 @@ use wiremock::{
     Mock, ResponseTemplate,
 };
 
-use crate::helpers::spawn_app;
+use crate::helpers::{create_unconfirmed_subscriber, spawn_app, TestApp};
 
 #[tokio::test]
-async fn confirm_subscription_with_valid_token() {
+async fn confirmations_without_token_are_rejected_with_a_400() {
+    let app = spawn_app().await;
+
+    let res = reqwest::get(format!("http://{}/subscriptions/confirm", app.address))
+        .await
+        .unwrap();
+
+    assert_eq!(res.status().as_u16(), 400);
+    assert_eq!(
+        res.headers().get("Content-Type").unwrap(),
+        "application/problem+json"
+    );
+    let body: serde_json::Value = res.json().await.unwrap();
+    assert_eq!(body["errors"][0]["field"], "subscription_token");
+}
+
+#[tokio::test]
+async fn confirmations_with_an_unknown_token_are_rejected_with_a_404() {
+    let app = spawn_app().await;
+
+    let res = app.get_subscription_confirm("not-a-real-token").await;
+
+    assert_eq!(res.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn clicking_on_the_confirmation_link_confirms_a_subscriber() {
     let app = spawn_app().await;
     let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
 
@@ -27,11 +53,9 @@ async fn confirm_subscription_with_valid_token() {
     assert_eq!(saved.status, "pending_confirmation");
 
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
-    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let confirmation_links = app.get_confirmation_links(email_request);
 
-    let res = app
-        .get_subscription_confirm(&body.get("HtmlBody").unwrap().as_str().unwrap())
-        .await;
+    let res = reqwest::get(confirmation_links.html).await.unwrap();
     assert_eq!(res.status(), 200);
 
     let saved = sqlx::query!("SELECT status FROM subscriptions")
@@ -40,3 +64,62 @@ async fn confirm_subscription_with_valid_token() {
         .expect("Failed to fetch saved subscription");
     assert_eq!(saved.status, "confirmed");
 }
+
+async fn create_pending_subscriber_token(app: &TestApp) -> String {
+    let links = create_unconfirmed_subscriber(app).await;
+    links
+        .html
+        .query_pairs()
+        .find(|(k, _)| k == "subscription_token")
+        .map(|(_, v)| v.into_owned())
+        .expect("No token in the confirmation link")
+}
+
+async fn subscriber_status(app: &TestApp) -> String {
+    sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription")
+        .status
+}
+
+#[tokio::test]
+async fn subscription_tokens_are_only_stored_as_hashes() {
+    let app = spawn_app().await;
+    let token = create_pending_subscriber_token(&app).await;
+
+    let stored = sqlx::query!("SELECT subscription_token_hash FROM subscription_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved token");
+
+    assert_ne!(stored.subscription_token_hash, token);
+    assert!(!stored.subscription_token_hash.contains(&token));
+}
+
+#[tokio::test]
+async fn confirmation_links_can_only_be_used_once() {
+    let app = spawn_app().await;
+    let token = create_pending_subscriber_token(&app).await;
+
+    let res = app.get_subscription_confirm(&token).await;
+    assert_eq!(res.status().as_u16(), 200);
+
+    let res = app.get_subscription_confirm(&token).await;
+    assert_eq!(res.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn expired_confirmation_links_are_rejected_with_a_410() {
+    let app = spawn_app().await;
+    let token = create_pending_subscriber_token(&app).await;
+    sqlx::query!("UPDATE subscription_tokens SET expires_at = now() - interval '1 second'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let res = app.get_subscription_confirm(&token).await;
+
+    assert_eq!(res.status().as_u16(), 410);
+    assert_eq!(subscriber_status(&app).await, "pending_confirmation");
+}
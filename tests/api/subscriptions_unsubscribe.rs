@@ -0,0 +1,203 @@
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{create_confirmed_subscriber, spawn_app, TestApp};
+
+async fn subscriber_status(app: &TestApp) -> String {
+    sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription")
+        .status
+}
+
+async fn subscriber_id(app: &TestApp) -> uuid::Uuid {
+    sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription")
+        .id
+}
+
+async fn unsubscribe_link(app: &TestApp) -> reqwest::Url {
+    let mut link =
+        reqwest::Url::parse(&app.unsubscribe_links.link(subscriber_id(app).await)).unwrap();
+    link.set_port(Some(app.port)).unwrap();
+    link
+}
+
+#[tokio::test]
+async fn newsletters_carry_one_click_unsubscribe_headers() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_newsletters(serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let expected_link = app.unsubscribe_links.link(subscriber_id(&app).await);
+
+    assert_eq!(
+        body["Headers"],
+        serde_json::json!([
+            {"Name": "List-Unsubscribe", "Value": format!("<{}>", expected_link)},
+            {"Name": "List-Unsubscribe-Post", "Value": "List-Unsubscribe=One-Click"},
+        ])
+    );
+    assert!(body["TextBody"].as_str().unwrap().contains(&expected_link));
+}
+
+#[tokio::test]
+async fn one_click_unsubscribe_marks_the_subscriber_as_unsubscribed() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    let res = reqwest::Client::new()
+        .post(unsubscribe_link(&app).await)
+        .form(&[("List-Unsubscribe", "One-Click")])
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(res.status().as_u16(), 200);
+    assert_eq!(subscriber_status(&app).await, "unsubscribed");
+}
+
+#[tokio::test]
+async fn following_the_unsubscribe_link_does_not_unsubscribe_on_its_own() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    let res = reqwest::get(unsubscribe_link(&app).await).await.unwrap();
+
+    assert_eq!(res.status().as_u16(), 200);
+    assert!(res.text().await.unwrap().contains("method=\"post\""));
+    assert_eq!(subscriber_status(&app).await, "confirmed");
+}
+
+#[tokio::test]
+async fn tampered_unsubscribe_tokens_are_rejected() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    let mut link = unsubscribe_link(&app).await;
+    let token = link.query_pairs().next().unwrap().1.into_owned();
+    link.set_query(Some(&format!("token={}x", token)));
+
+    let res = reqwest::Client::new().post(link).send().await.unwrap();
+
+    assert_eq!(res.status().as_u16(), 400);
+    assert_eq!(subscriber_status(&app).await, "confirmed");
+}
+
+#[tokio::test]
+async fn the_api_unsubscribes_with_a_valid_token() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    let mut link = unsubscribe_link(&app).await;
+    link.set_path("/api/v1/subscriptions/unsubscribe");
+    let res = reqwest::Client::new().post(link).send().await.unwrap();
+
+    assert_eq!(res.status().as_u16(), 200);
+    assert_eq!(subscriber_status(&app).await, "unsubscribed");
+}
+
+#[tokio::test]
+async fn the_api_reports_invalid_unsubscribe_tokens_as_problems() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    let res = reqwest::Client::new()
+        .post(format!(
+            "http://{}/api/v1/subscriptions/unsubscribe?token=not-a-token",
+            app.address
+        ))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(res.status().as_u16(), 400);
+    assert_eq!(
+        res.headers().get("Content-Type").unwrap(),
+        "application/problem+json"
+    );
+    let body: serde_json::Value = res.json().await.unwrap();
+    assert_eq!(body["errors"][0]["field"], "token");
+    assert_eq!(subscriber_status(&app).await, "confirmed");
+}
+
+#[tokio::test]
+async fn the_unsubscribe_form_reports_invalid_tokens_as_problems() {
+    let app = spawn_app().await;
+
+    let res = reqwest::get(format!(
+        "http://{}/subscriptions/unsubscribe?token=not-a-token",
+        app.address
+    ))
+    .await
+    .unwrap();
+
+    assert_eq!(res.status().as_u16(), 400);
+    assert_eq!(
+        res.headers().get("Content-Type").unwrap(),
+        "application/problem+json"
+    );
+}
+
+#[tokio::test]
+async fn unsubscribed_subscribers_do_not_receive_newsletters() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    reqwest::Client::new()
+        .post(unsubscribe_link(&app).await)
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_newsletters(serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+    app.dispatch_all_pending_emails().await;
+}